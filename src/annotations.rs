@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use crate::error::Result;
+use crate::json::json_text;
+use crate::marks::Mark;
+use crate::yamldiff::{Comparison,Diff,Diffs,DiffOptions,DocKey};
+
+/** A difference, reported against the modified file */
+struct Annotation<'a> {
+    kind: &'static str,
+    file: &'a str,
+    mark: Option<Mark>,
+    message: String
+}
+
+/**
+ * An annotation of the modified file for each difference. Removed values are reported
+ * at the position of their closest remaining ancestor in the modified document.
+ */
+fn annotations<'a>(comparison: &Comparison, opts: &'a DiffOptions, diffs: &'a Diffs) -> Vec<Annotation<'a>> {
+    let pairs: HashMap<&DocKey,&DocKey> = comparison.document_pairs().into_iter().collect();
+    diffs.iter().map(|diff| match diff {
+        Diff::Add(lav) | Diff::AddEmpty(lav) => Annotation{
+            kind: if matches!(diff,Diff::AddEmpty(_)) {"add-empty"} else {"add"},
+            file: &lav.loc.fname,
+            mark: lav.loc.mark,
+            message: format!("{} {}: added {}",lav.loc.doc,lav.loc.path,json_text(&lav.value))
+        },
+        Diff::Remove(lav) | Diff::RemoveEmpty(lav) => Annotation{
+            kind: if matches!(diff,Diff::RemoveEmpty(_)) {"remove-empty"} else {"remove"},
+            file: &opts.file2,
+            mark: pairs.get(&*lav.loc.doc).and_then(|doc| comparison.position(doc,&lav.loc.path,false)),
+            message: format!("{} {}: removed {}",lav.loc.doc,lav.loc.path,json_text(&lav.value))
+        },
+        Diff::Differ(lav1,lav2) => Annotation{
+            kind: "change",
+            file: &lav2.loc.fname,
+            mark: lav2.loc.mark,
+            message: format!("{} {}: changed from {} to {}",lav2.loc.doc,lav2.loc.path,json_text(&lav1.value),json_text(&lav2.value))
+        },
+        Diff::Move(lav1,lav2) => Annotation{
+            kind: "move",
+            file: &lav2.loc.fname,
+            mark: lav2.loc.mark,
+            message: if lav1.loc.path == lav2.loc.path {
+                format!("{} {}: moved",lav2.loc.doc,lav2.loc.path)
+            } else {
+                format!("{} {}: moved from {}",lav2.loc.doc,lav2.loc.path,lav1.loc.path)
+            }
+        },
+        Diff::DocumentRenamed(loc1,loc2) => Annotation{
+            kind: "rename",
+            file: &loc2.fname,
+            mark: comparison.position(&loc2.doc,&loc2.path,false),
+            message: format!("document {} renamed to {}",loc1.doc,loc2.doc)
+        }
+    }).collect()
+}
+
+/** Escape data for a GitHub workflow command, or a property of one */
+fn escape(text: &str, property: bool) -> String {
+    let text = text.replace('%',"%25").replace('\r',"%0D").replace('\n',"%0A");
+    if property { text.replace(':',"%3A").replace(',',"%2C") } else { text }
+}
+
+/** A GitHub Actions `::warning` workflow command for each difference */
+pub fn github_annotations(comparison: &Comparison, opts: &DiffOptions, diffs: &Diffs) -> Vec<String> {
+    annotations(comparison,opts,diffs).into_iter().map(|annotation| {
+        let position = match annotation.mark {
+            Some(mark) => format!(",line={},col={}",mark.line,mark.col),
+            None => String::new()
+        };
+        format!("::warning file={}{},title={}::{}",escape(annotation.file,true),position,
+            escape(&format!("yamldiff {}",annotation.kind),true),escape(&annotation.message,false))
+    }).collect()
+}
+
+#[derive(Serialize)]
+struct GitlabLines {
+    begin: usize
+}
+
+#[derive(Serialize)]
+struct GitlabLocation<'a> {
+    path: &'a str,
+    lines: GitlabLines
+}
+
+#[derive(Serialize)]
+struct GitlabIssue<'a> {
+    description: String,
+    check_name: String,
+    fingerprint: String,
+    severity: &'static str,
+    location: GitlabLocation<'a>
+}
+
+/** A 64 bit FNV-1a hash, which unlike the standard library's hashers is stable between releases */
+fn fingerprint(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}",hash)
+}
+
+/**
+ * A GitLab Code Quality report of the differences. Each is identified by a fingerprint of
+ * its file, kind and message, so that the same difference found by successive pipelines
+ * is recognised as the same issue.
+ */
+pub fn gitlab_report(comparison: &Comparison, opts: &DiffOptions, diffs: &Diffs) -> Result<String> {
+    let issues: Vec<GitlabIssue> = annotations(comparison,opts,diffs).into_iter().map(|annotation| GitlabIssue{
+        fingerprint: fingerprint(&[annotation.file,annotation.kind,&annotation.message]),
+        check_name: format!("yamldiff-{}",annotation.kind),
+        description: annotation.message,
+        severity: "minor",
+        location: GitlabLocation{
+            path: annotation.file,
+            lines: GitlabLines{begin: annotation.mark.map(|mark| mark.line).unwrap_or(1)}
+        }
+    }).collect();
+    Ok(serde_json::to_string_pretty(&issues)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json,Value};
+    use yaml_rust::YamlLoader;
+    use crate::marks::mark_documents;
+
+    fn comparison(opts: &DiffOptions, text1: &str, text2: &str) -> Comparison {
+        let y1 = YamlLoader::load_from_str(text1).unwrap();
+        let y2 = YamlLoader::load_from_str(text2).unwrap();
+        Comparison::new(opts,&None,y1,y2).unwrap().with_marks(mark_documents(text1).unwrap(),mark_documents(text2).unwrap())
+    }
+
+    #[test]
+    fn test_github_annotations() {
+        let opts = DiffOptions::new("f1.yaml","dir/f2,new.yaml");
+        let comparison = comparison(&opts,"a: 1\nb:\n  c: x\n  d: y\n","a: 2\nb:\n  c: \"x\\ny\"\n");
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        assert_eq!(vec![
+            "::warning file=dir/f2%2Cnew.yaml,line=1,col=1,title=yamldiff change::[0] a: changed from 1 to 2",
+            "::warning file=dir/f2%2Cnew.yaml,line=3,col=3,title=yamldiff change::[0] b.c: changed from \"x\" to \"x\\ny\"",
+            "::warning file=dir/f2%2Cnew.yaml,line=2,col=1,title=yamldiff remove::[0] b.d: removed \"y\""
+        ],github_annotations(&comparison,&opts,&diffs));
+    }
+
+    #[test]
+    fn test_gitlab_report() {
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = comparison(&opts,"a: 1\n","a: 1\nb:\n  c: [x]\n");
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        let report: Value = serde_json::from_str(&gitlab_report(&comparison,&opts,&diffs).unwrap()).unwrap();
+        assert_eq!(json!([{
+            "description": "[0] b.c[0]: added \"x\"",
+            "check_name": "yamldiff-add",
+            "fingerprint": fingerprint(&["f2.yaml","add","[0] b.c[0]: added \"x\""]),
+            "severity": "minor",
+            "location": {"path": "f2.yaml", "lines": {"begin": 3}}
+        }]),report);
+        assert_ne!(fingerprint(&["ab","c"]),fingerprint(&["a","bc"]));
+    }
+}
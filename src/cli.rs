@@ -0,0 +1,223 @@
+use clap::{ArgEnum,Parser,Subcommand};
+use std::fs;
+use std::io;
+use terminal_size::{terminal_size,Width};
+use yamldiff::annotations::{github_annotations,gitlab_report};
+use yamldiff::context::{Context,ContextOptions};
+use yamldiff::error::{Result,ResultExt};
+use yamldiff::html::html_report;
+use yamldiff::json::{diffs_to_json_string,yaml_to_json};
+use yamldiff::jsonpatch::json_patches;
+use yamldiff::junit::junit_report;
+use yamldiff::keypath::KeyPath;
+use yamldiff::markdown::markdown_report;
+use yamldiff::merge::{do_merge,MergeOpts};
+use yamldiff::mergepatch::merge_patches;
+use yamldiff::patch::{do_patch,PatchOpts};
+use yamldiff::sidebyside::{write_side_by_side,DEFAULT_WIDTH};
+use yamldiff::strategy::Strategy;
+use yamldiff::tree::write_tree;
+use yamldiff::yamldiff::{load_file_marked,write_diffs_in_context,Comparison,Diffs,DiffOptions,DiffStats,YamlEmitter};
+
+/* Command line options */
+#[derive(Parser)]
+#[clap(args_conflicts_with_subcommands=true,subcommand_negates_reqs=true)]
+pub struct Opts {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(required=true,help="Original YAML file")]
+    file1: Option<String>,
+    #[clap(required=true,help="Modified YAML file")]
+    file2: Option<String>,
+    #[clap(short,long,help="Compare kubernetes yaml documents")]
+    k8s: bool,
+    #[clap(short,long,help="Don't produce coloured output")]
+    no_colour: bool,
+    #[clap(short('x'),long,multiple_occurrences(true),help="Exclude YAML document paths matching regex")]
+    exclude: Vec<String>,
+    #[clap(short('f'),long,help="File name of strategy file")]
+    strategy: Option<String>,
+    #[clap(short('c'),long,help="Display the number of differences only, rather than the differences themselves")]
+    count: bool,
+    #[clap(long,requires("count"),help="Count each value within a hash or array reported as a whole, rather than the hash or array")]
+    count_leaves: bool,
+    #[clap(short('l'),long,help="Align arrays by longest common subsequence, rather than by position")]
+    lcs: bool,
+    #[clap(long,help="Don't report array elements that have moved position")]
+    ignore_moves: bool,
+    #[clap(long,help="Report a hash or array found in only one file as a whole, rather than by each of its values")]
+    subtrees: bool,
+    #[clap(long,help="Treat null values and empty hashes and arrays as equivalent to values that are missing")]
+    empty_as_missing: bool,
+    #[clap(short('s'),long,value_name("THRESHOLD"),value_parser(parse_threshold),help="Pair unmatched documents with a similarity (0 to 1) of at least this threshold")]
+    similarity: Option<f64>,
+    #[clap(short('o'),long,arg_enum,value_name("FORMAT"),default_value("text"),help="Format in which to output the differences")]
+    output: OutputFormat,
+    #[clap(long,help="Show text output in two columns, the original file on the left and the modified file on the right")]
+    side_by_side: bool,
+    #[clap(short('t'),long,conflicts_with("side-by-side"),help="Show text output as a tree, with the differences beneath the keys containing them")]
+    tree: bool,
+    #[clap(short('w'),long,value_name("COLUMNS"),requires("side-by-side"),help="Width of side by side output, rather than that of the terminal")]
+    width: Option<usize>,
+    #[clap(short('C'),long,conflicts_with_all(&["tree","side-by-side"]),help="Show fields identifying each array element containing a difference, such as its name")]
+    context: bool,
+    #[clap(long,value_name("PATH"),multiple_occurrences(true),requires("context"),
+           help="Field identifying array elements, instead of name and metadata.name (repeatable)")]
+    context_field: Vec<String>,
+    #[clap(long,value_name("N"),default_value("0"),requires("context"),help="Also show up to N unchanged values beside each difference")]
+    context_siblings: usize
+}
+
+/** A similarity threshold, which must be between 0 and 1 */
+fn parse_threshold(arg: &str) -> std::result::Result<f64,String> {
+    match arg.parse::<f64>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => Ok(threshold),
+        Ok(_) => Err("must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+/* Subcommands, used instead of comparing two files */
+#[derive(Subcommand)]
+pub enum Command {
+    /// Apply a JSON Patch or yamldiff patch records to a YAML file, writing the result to standard output
+    Patch(PatchOpts),
+    /// Merge the changes made to a common base by two YAML files, writing the result to standard output
+    Merge(MergeOpts)
+}
+
+/** Formats in which differences can be output */
+#[derive(ArgEnum,Clone,Copy,Debug,PartialEq,Eq)]
+pub enum OutputFormat {
+    /** Human readable, optionally coloured, text */
+    Text,
+    /** A JSON report of each difference and the totals */
+    Json,
+    /** An RFC 6902 JSON Patch for each pair of matched documents, one per line */
+    JsonPatch,
+    /** An RFC 7386 JSON Merge Patch for each pair of matched documents, one per line */
+    MergePatch,
+    /** A YAML stream of the sparse documents to deep merge onto each of the original documents */
+    Overlay,
+    /** A GitHub Actions warning annotation of the modified file for each difference */
+    Github,
+    /** A GitLab Code Quality report of the differences in the modified file */
+    Gitlab,
+    /** A JUnit XML report with a test case for each document, which fails if it has differences */
+    Junit,
+    /** A Markdown report of the differences in each document, such as for a pull request comment */
+    Markdown,
+    /** A self-contained HTML page showing each pair of documents side by side */
+    Html
+}
+
+impl Opts {
+    fn diff_options(&self) -> DiffOptions {
+        DiffOptions {
+            file1: self.file1.clone().unwrap_or_default(),
+            file2: self.file2.clone().unwrap_or_default(),
+            k8s: self.k8s,
+            exclude: self.exclude.clone(),
+            lcs: self.lcs,
+            ignore_moves: self.ignore_moves,
+            similarity: self.similarity,
+            subtrees: self.subtrees || matches!(self.output,OutputFormat::JsonPatch|OutputFormat::MergePatch|OutputFormat::Overlay),
+            empty_as_missing: self.empty_as_missing
+        }
+    }
+    fn context_options(&self) -> Option<ContextOptions> {
+        if !self.context {
+            return None
+        }
+        let mut context = ContextOptions{siblings: self.context_siblings, ..ContextOptions::default()};
+        if !self.context_field.is_empty() {
+            context.fields = self.context_field.iter().map(|field| KeyPath::parse(field)).collect();
+        }
+        Some(context)
+    }
+    fn parse_strategy(&self) -> Result<Option<Strategy>> {
+        match &self.strategy {
+            None => Ok(None),
+            Some(fname) => Opts::parse_strategy_file(fname).chain_err(|| format!("can't load {}",fname))
+        }
+    }
+
+    fn parse_strategy_file(fname: &str) -> Result<Option<Strategy>> {
+        let yaml = fs::read_to_string(fname)?;
+        Ok(Some(Strategy::from_str(&yaml)?))
+    }
+}
+
+fn show_diffs(opts: &Opts, diffs: &Diffs, stats: &DiffStats, context: Option<&Context>) -> Result<()> {
+    if opts.count {
+        println!("{}",stats);
+        Ok(())
+    } else {
+        write_diffs_in_context(&mut io::stdout().lock(),!opts.no_colour,diffs,context)
+    }
+}
+
+/** Run the subcommand given on the command line, or else compare the two files */
+pub fn run(opts: &Opts) -> Result<i32> {
+    match &opts.command {
+        Some(Command::Patch(patch_opts)) => do_patch(patch_opts),
+        Some(Command::Merge(merge_opts)) => do_merge(merge_opts),
+        None => do_diff(opts)
+    }
+}
+
+fn do_diff(opts: &Opts) -> Result<i32> {
+    if opts.output != OutputFormat::Text {
+        let layouts = [(opts.side_by_side,"--side-by-side"),(opts.tree,"--tree"),(opts.context,"--context")];
+        if let Some((_,flag)) = layouts.iter().find(|(set,_)| *set) {
+            return Err(format!("{} only applies to text output, and can't be used with --output {}",flag,opts.output.to_possible_value().map(|value| value.get_name()).unwrap_or_default()).into())
+        }
+    }
+    let strategy = opts.parse_strategy()?;
+    let diff_opts = opts.diff_options();
+    let (y1,marks1) = load_file_marked(&diff_opts.file1).chain_err(|| format!("while parsing {}",&diff_opts.file1))?;
+    let (y2,marks2) = load_file_marked(&diff_opts.file2).chain_err(|| format!("while parsing {}",&diff_opts.file2))?;
+    let comparison = Comparison::new(&diff_opts, &strategy, y1, y2)?.with_marks(marks1, marks2);
+    let diffs = comparison.diffs(&diff_opts, &strategy)?;
+    let stats = if opts.count_leaves { DiffStats::from_leaves(&diffs,&diff_opts) } else { DiffStats::from(&diffs) };
+    match opts.output {
+        OutputFormat::Text if opts.side_by_side && !opts.count => {
+            let width = opts.width.or_else(|| terminal_size().map(|(Width(width),_)| width as usize)).unwrap_or(DEFAULT_WIDTH);
+            write_side_by_side(&mut io::stdout().lock(),!opts.no_colour,width,&comparison,&diff_opts,&diffs)?
+        }
+        OutputFormat::Text if opts.tree && !opts.count => write_tree(&mut io::stdout().lock(),!opts.no_colour,&comparison,&diffs)?,
+        OutputFormat::Text => {
+            let context_opts = opts.context_options();
+            let context = context_opts.as_ref().map(|context_opts| Context::new(&comparison,context_opts,&diffs));
+            show_diffs(opts,&diffs,&stats,context.as_ref())?
+        }
+        OutputFormat::Json => println!("{}",diffs_to_json_string(&diffs,&stats,opts.count)?),
+        OutputFormat::JsonPatch => {
+            for patch in json_patches(&comparison,&diffs)? {
+                println!("{}",serde_json::to_string(&patch)?)
+            }
+        }
+        OutputFormat::MergePatch => {
+            for patch in merge_patches(&comparison,&diffs)? {
+                println!("{}",serde_json::to_string(&yaml_to_json(&patch))?)
+            }
+        }
+        OutputFormat::Overlay => {
+            for patch in merge_patches(&comparison,&diffs)? {
+                let mut out = String::new();
+                YamlEmitter::new(&mut out).dump(&patch)?;
+                println!("{}",out)
+            }
+        }
+        OutputFormat::Github => {
+            for annotation in github_annotations(&comparison,&diff_opts,&diffs) {
+                println!("{}",annotation)
+            }
+        }
+        OutputFormat::Gitlab => println!("{}",gitlab_report(&comparison,&diff_opts,&diffs)?),
+        OutputFormat::Junit => println!("{}",junit_report(&comparison,&diff_opts,&diffs)?),
+        OutputFormat::Markdown => println!("{}",markdown_report(&comparison,&diff_opts,&diffs)?),
+        OutputFormat::Html => println!("{}",html_report(&comparison,&diff_opts,&strategy,&diffs)?)
+    }
+    Ok(if stats.total == 0 {0} else {1})
+}
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use yaml_rust::Yaml;
+use crate::keypath::{ItemKey,KeyPath,KeyPathFuncs};
+use crate::yamldiff::{Comparison,Diff,Diffs,DocKey,Location};
+
+/** Fields identifying array elements when no others are given */
+pub const DEFAULT_FIELDS: [&str; 2] = ["name","metadata.name"];
+
+/** Which unchanged values are shown with each difference */
+#[derive(Clone,Debug)]
+pub struct ContextOptions {
+    /** Paths, relative to an array element, of fields that identify it */
+    pub fields: Vec<KeyPath>,
+    /** The number of unchanged scalar values beside a difference to show */
+    pub siblings: usize
+}
+
+impl Default for ContextOptions {
+    fn default() -> ContextOptions {
+        ContextOptions{fields: DEFAULT_FIELDS.iter().map(|field| KeyPath::parse(field)).collect(),siblings: 0}
+    }
+}
+
+/**
+ * Unchanged values that help identify where a difference is: the fields identifying
+ * each array element containing it, or the document if it's identified by position,
+ * and optionally the values beside it.
+ */
+pub struct Context<'c> {
+    comparison: &'c Comparison,
+    opts: &'c ContextOptions,
+    /** Paths of the differences in each document, resolved against it */
+    changed: HashSet<(bool,DocKey,KeyPath)>
+}
+
+impl<'c> Context<'c> {
+    pub fn new(comparison: &'c Comparison, opts: &'c ContextOptions, diffs: &'c Diffs) -> Context<'c> {
+        let mut context = Context{comparison,opts,changed: HashSet::new()};
+        let mut changed = HashSet::new();
+        for diff in diffs {
+            let locations: Vec<(bool,&Location)> = match diff {
+                Diff::Add(lav) | Diff::AddEmpty(lav) => vec![(false,&lav.loc)],
+                Diff::Remove(lav) | Diff::RemoveEmpty(lav) => vec![(true,&lav.loc)],
+                Diff::Differ(lav1,lav2) | Diff::Move(lav1,lav2) => vec![(true,&lav1.loc),(false,&lav2.loc)],
+                Diff::DocumentRenamed(_,_) => Vec::new()
+            };
+            for (original,loc) in locations {
+                if let Some((_,path)) = context.resolve(original,loc) {
+                    changed.insert((original,(*loc.doc).clone(),path));
+                }
+            }
+        }
+        context.changed = changed;
+        context
+    }
+
+    /** The document a location is in, and the path with any element matches resolved against it */
+    fn resolve(&self, original: bool, loc: &Location) -> Option<(Cow<'c,Yaml>,KeyPath)> {
+        let doc = self.comparison.document(&loc.doc,original)?;
+        let path = loc.path.resolved(&doc).ok()?;
+        Some((doc,path))
+    }
+
+    /** The identifying fields of the array element or document at a path */
+    fn fields(&self, doc: &Yaml, element: &KeyPath, diff_path: &KeyPath, lines: &mut Vec<(KeyPath,Yaml)>) {
+        let Ok(value) = doc.get_at_path(element.clone()) else { return };
+        for field in &self.opts.fields {
+            let path = KeyPath(element.0.iter().chain(&field.0).cloned().collect());
+            if path == *diff_path { continue; }
+            if let Ok(found) = value.get_at_path(field.clone()) {
+                if !matches!(found,Yaml::Hash(_)|Yaml::Array(_)|Yaml::BadValue) {
+                    lines.push((path,found.clone()));
+                }
+            }
+        }
+    }
+
+    /** Up to the configured number of unchanged scalar values closest to the value at a path */
+    fn siblings(&self, original: bool, doc_key: &DocKey, doc: &Yaml, path: &KeyPath, lines: &mut Vec<(KeyPath,Yaml)>) {
+        let Some(last) = path.0.last() else { return };
+        let parent = path.truncated(path.0.len()-1);
+        let entries: Vec<(ItemKey,&Yaml)> = match doc.get_at_path(parent.clone()) {
+            Ok(Yaml::Hash(hash)) => hash.iter().filter_map(|(key,value)| key.as_str().map(|key| (ItemKey::Key(key.to_string()),value))).collect(),
+            Ok(Yaml::Array(arr)) => arr.iter().enumerate().map(|(index,value)| (ItemKey::Index(index),value)).collect(),
+            _ => return
+        };
+        let position = entries.iter().position(|(key,_)| key == last).unwrap_or(entries.len());
+        let mut candidates: Vec<(usize,KeyPath,&Yaml)> = entries.into_iter().enumerate().filter_map(|(n,(key,value))| {
+            let sibling = parent.push(key);
+            let unchanged = !self.changed.contains(&(original,doc_key.clone(),sibling.clone()));
+            let shown = lines.iter().any(|(path,_)| *path == sibling);
+            (n != position && unchanged && !shown && !matches!(value,Yaml::Hash(_)|Yaml::Array(_))).then_some((n,sibling,value))
+        }).collect();
+        candidates.sort_by_key(|(n,_,_)| (n.abs_diff(position),*n));
+        candidates.truncate(self.opts.siblings);
+        candidates.sort_by_key(|(n,_,_)| *n);
+        lines.extend(candidates.into_iter().map(|(_,path,value)| (path,value.clone())));
+    }
+
+    /**
+     * The unchanged values shown with a difference, by their path in the document of
+     * the original file, or of the modified file for a value added.
+     */
+    pub fn values(&self, diff: &Diff) -> Vec<(KeyPath,Yaml)> {
+        let (original,loc) = match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => (false,&lav.loc),
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) | Diff::Differ(lav,_) | Diff::Move(lav,_) => (true,&lav.loc),
+            Diff::DocumentRenamed(_,_) => return Vec::new()
+        };
+        let Some((doc,path)) = self.resolve(original,loc) else { return Vec::new() };
+        let mut lines = Vec::new();
+        if matches!(*loc.doc,DocKey::Position(_)) {
+            self.fields(&doc,&KeyPath::new(),&path,&mut lines);
+        }
+        for (len,item) in path.0.iter().enumerate() {
+            if matches!(item,ItemKey::Index(_)) && matches!(loc.path.0.get(len),Some(ItemKey::Index(_))) {
+                self.fields(&doc,&path.truncated(len+1),&path,&mut lines);
+            }
+        }
+        if self.opts.siblings > 0 {
+            self.siblings(original,&loc.doc,&doc,&path,&mut lines);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+    use crate::yamldiff::DiffOptions;
+
+    fn values(opts: &ContextOptions, text1: &str, text2: &str) -> Vec<Vec<String>> {
+        let diff_opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = Comparison::new(&diff_opts,&None,YamlLoader::load_from_str(text1).unwrap(),YamlLoader::load_from_str(text2).unwrap()).unwrap();
+        let diffs = comparison.diffs(&diff_opts,&None).unwrap();
+        let context = Context::new(&comparison,opts,&diffs);
+        diffs.iter().map(|diff| context.values(diff).iter().map(|(path,value)| format!("{} = {:?}",path,value)).collect()).collect()
+    }
+
+    #[test]
+    fn test_identifying_fields() {
+        let text1 = "metadata: {name: pod}\ncontainers:\n- {name: app, image: a1}\n- {name: sidecar, image: s1}\n";
+        let text2 = "metadata: {name: pod}\ncontainers:\n- {name: app, image: a1}\n- {name: proxy, image: s2}\n";
+        assert_eq!(vec![
+            vec!["metadata.name = String(\"pod\")".to_string()],
+            vec!["metadata.name = String(\"pod\")".to_string(),"containers[1].name = String(\"sidecar\")".to_string()]
+        ],values(&ContextOptions::default(),text1,text2));
+    }
+
+    #[test]
+    fn test_siblings() {
+        let opts = ContextOptions{fields: Vec::new(),siblings: 2};
+        assert_eq!(vec![
+            vec!["b = Integer(2)".to_string(),"d = Integer(4)".to_string()]
+        ],values(&opts,"a: 1\nb: 2\nc: 3\nd: 4\ne: 5\n","a: 1\nb: 2\nc: x\nd: 4\ne: 5\n"));
+        assert_eq!(vec![
+            vec!["a = Integer(1)".to_string(),"b = Integer(2)".to_string()],
+            vec!["b = Integer(2)".to_string(),"e = Integer(5)".to_string()]
+        ],values(&opts,"a: 1\nb: 2\nc: 3\nd: 4\ne: 5\n","a: 1\nb: 2\nc: x\nd: y\ne: 5\n"));
+    }
+}
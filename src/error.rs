@@ -1,11 +1,11 @@
-use serde_yaml;
-use regex;
-
 error_chain!{
     foreign_links {
         Io(std::io::Error);
+        Fmt(std::fmt::Error);
         Yaml(yaml_rust::ScanError);
         SerdeYaml(serde_yaml::Error);
+        SerdeJson(serde_json::Error);
+        YamlEmit(yaml_rust::EmitError);
         Regex(regex::Error);
     }
     errors {
@@ -21,6 +21,30 @@ error_chain!{
             description("invalid array index in YAML document")
             display("invalid array index {} at '{}' in YAML document",index,key)
         }
+        InvalidPatch(reason: String) {
+            description("invalid patch")
+            display("invalid patch: {}",reason)
+        }
+        PatchConflicts(conflicts: Vec<String>) {
+            description("patch conflicts with the file being patched")
+            display("patch conflicts with the file being patched:\n  {}",conflicts.join("\n  "))
+        }
+        DocumentNotFound(selector: String) {
+            description("document not found")
+            display("document '{}' not found",selector)
+        }
+        DuplicateDocument(key: String) {
+            description("more than one document has the same key")
+            display("more than one document has the key '{}'",key)
+        }
+        NullInMergePatch(path: String) {
+            description("a value set to null can't be recorded in a merge patch")
+            display("'{}' is set to null, which can't be recorded in a merge patch, where null removes a value",path)
+        }
+        InvalidStrategy(reason: String) {
+            description("invalid strategy")
+            display("invalid strategy: {}",reason)
+        }
         UnknownRenameField(field: String) {
             description("Field found in rename directive is not recognised")
             display("Unknown field '{}' not found in rename directive",field)
@@ -0,0 +1,328 @@
+use std::collections::{HashMap,HashSet};
+use std::fmt::Write;
+use yaml_rust::Yaml;
+use crate::error::Result;
+use crate::json::scalar_text;
+use crate::keypath::{scalar_string,ItemKey,KeyPath};
+use crate::strategy::Strategy;
+use crate::yamldiff::{align_arrays,Comparison,Diff,Diffs,DiffOptions,DiffStats,DocKey,DocumentDiffs};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em 2em; }
+nav ul { list-style: none; padding: 0; columns: 3; }
+nav li::before { content: \"\\25CF\"; margin-right: 0.4em; color: #bbb; }
+nav li.changed::before { color: #d4a72c; }
+nav li.added::before { color: #2da44e; }
+nav li.removed::before { color: #cf222e; }
+section { margin-top: 2em; }
+.count { color: #666; }
+.files, .row { display: grid; grid-template-columns: 1fr 1fr; column-gap: 1em; }
+.files { font-weight: bold; border-bottom: 1px solid #ccc; }
+.row { font-family: monospace; white-space: pre-wrap; }
+summary.row { cursor: pointer; list-style: none; }
+summary.row::-webkit-details-marker { display: none; }
+details:not([open]) > summary .cell:not(.missing)::after { content: \" \\2026\"; color: #888; }
+.cell { padding-left: calc(var(--depth) * 2ch); }
+.missing { background: #f6f8fa; }
+.removed { background: #ffebe9; }
+.added { background: #dafbe1; }
+.changed { background: #fff8c5; }
+.moved { background: #ddf4ff; }
+";
+
+/** Escape text for HTML content or attribute values */
+fn escape(text: &str) -> String {
+    text.replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;").replace('"',"&quot;")
+}
+
+/**
+ * The values with differences in one side's document, highlighted with a class by their
+ * path with any element matches resolved, along with every path containing one.
+ */
+#[derive(Default)]
+struct Highlights {
+    classes: HashMap<KeyPath,&'static str>,
+    containing: HashSet<KeyPath>
+}
+
+impl Highlights {
+    fn insert(&mut self, doc: Option<&Yaml>, path: &KeyPath, class: &'static str) {
+        let path = doc.and_then(|doc| path.resolved(doc).ok()).unwrap_or_else(|| path.clone());
+        for len in 0..path.0.len() {
+            self.containing.insert(path.truncated(len));
+        }
+        self.classes.entry(path).or_insert(class);
+    }
+
+    fn class(&self, path: &KeyPath) -> Option<&'static str> {
+        self.classes.get(path).copied()
+    }
+
+    /** True if the value at the path, or any value within it, is highlighted */
+    fn any_within(&self, path: &KeyPath) -> bool {
+        self.classes.contains_key(path) || self.containing.contains(path)
+    }
+}
+
+/** A line of one side's rendering of a value */
+struct Line {
+    depth: usize,
+    text: String,
+    class: Option<&'static str>
+}
+
+/** A value of one side of a pair being rendered, at its path in that side's document */
+#[derive(Clone,Copy)]
+struct Side<'y,'p> {
+    value: &'y Yaml,
+    path: &'p KeyPath,
+    class: Option<&'static str>
+}
+
+fn key_text(key: &Yaml) -> String {
+    scalar_string(key).unwrap_or_else(|| scalar_text(key))
+}
+
+fn cell(line: Option<&Line>) -> String {
+    match line {
+        Some(line) => {
+            let class = line.class.map(|class| format!(" {}",class)).unwrap_or_default();
+            format!("<div class=\"cell{}\" style=\"--depth:{}\">{}</div>",class,line.depth,escape(&line.text))
+        }
+        None => "<div class=\"cell missing\"></div>".to_string()
+    }
+}
+
+/** Renders a pair of documents side by side, aligned as they were compared */
+struct Renderer<'a> {
+    opts: &'a DiffOptions,
+    strategy: &'a Option<Strategy>,
+    doc: Option<&'a DocKey>,
+    left: Highlights,
+    right: Highlights,
+    out: String
+}
+
+impl<'a> Renderer<'a> {
+    fn rows(&mut self, left: &[Line], right: &[Line]) -> Result<()> {
+        for n in 0..left.len().max(right.len()) {
+            let (left,right) = (cell(left.get(n)),cell(right.get(n)));
+            writeln!(self.out,"<div class=\"row\">{}{}</div>",left,right)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * The lines of one side's value, labelled with its key or as an array element,
+     * rendered without reference to the other side.
+     */
+    fn lines(highlights: &Highlights, label: &str, side: Side, depth: usize, lines: &mut Vec<Line>) {
+        let class = highlights.class(side.path).or(side.class);
+        let text = |value: &str| if label.is_empty() { value.to_string() } else { format!("{} {}",label,value) };
+        match side.value {
+            Yaml::Hash(hash) if !hash.is_empty() => {
+                let depth = if label.is_empty() { depth } else { lines.push(Line{depth,text: label.to_string(),class}); depth+1 };
+                for (key,value) in hash {
+                    let key = key_text(key);
+                    let path = side.path.push(ItemKey::Key(key.clone()));
+                    Renderer::lines(highlights,&format!("{}:",key),Side{value,path: &path,class},depth,lines);
+                }
+            }
+            Yaml::Array(arr) if !arr.is_empty() => {
+                let depth = if label.is_empty() { depth } else { lines.push(Line{depth,text: label.to_string(),class}); depth+1 };
+                for (index,value) in arr.iter().enumerate() {
+                    let path = side.path.push(ItemKey::Index(index));
+                    Renderer::lines(highlights,"-",Side{value,path: &path,class},depth,lines);
+                }
+            }
+            Yaml::Hash(_) => lines.push(Line{depth,text: text("{}"),class}),
+            Yaml::Array(_) => lines.push(Line{depth,text: text("[]"),class}),
+            Yaml::String(s) if s.contains('\n') => {
+                lines.push(Line{depth,text: text("|"),class});
+                for line in s.trim_end_matches('\n').split('\n') {
+                    lines.push(Line{depth: depth+1,text: line.to_string(),class});
+                }
+            }
+            value => lines.push(Line{depth,text: text(&scalar_text(value)),class})
+        }
+    }
+
+    /** A container present on both sides, collapsed unless there are differences within it */
+    fn container<F>(&mut self, label: &str, left: Side, right: Side, depth: usize, children: F) -> Result<()>
+            where F: FnOnce(&mut Renderer<'a>,usize) -> Result<()> {
+        if label.is_empty() {
+            return children(self,depth)
+        }
+        let open = self.left.any_within(left.path) || self.right.any_within(right.path) || left.class.is_some() || right.class.is_some();
+        let header = |highlights: &Highlights, side: Side| Line{depth,text: label.to_string(),class: highlights.class(side.path).or(side.class)};
+        let (left_header,right_header) = (header(&self.left,left),header(&self.right,right));
+        writeln!(self.out,"<details{}><summary class=\"row\">{}{}</summary>",if open {" open"} else {""},cell(Some(&left_header)),cell(Some(&right_header)))?;
+        children(self,depth+1)?;
+        writeln!(self.out,"</details>")?;
+        Ok(())
+    }
+
+    /**
+     * Render the values at a path of each side, with those at the same path within them
+     * on the same row. The path is that of the original side as the values were compared.
+     */
+    fn pair(&mut self, label: &str, path: &KeyPath, left: Option<Side>, right: Option<Side>, depth: usize) -> Result<()> {
+        let sides = left.zip(right).map(|(left,right)| {
+            (Side{class: self.left.class(left.path).or(left.class),..left},Side{class: self.right.class(right.path).or(right.class),..right})
+        });
+        match sides.map(|(left,right)| (left,right,left.value,right.value)) {
+            Some((left,right,Yaml::Hash(hash1),Yaml::Hash(hash2))) if !hash1.is_empty() && !hash2.is_empty() => {
+                self.container(label,left,right,depth,|renderer,depth| {
+                    let keys = hash1.keys().chain(hash2.keys().filter(|key| !hash1.contains_key(key)));
+                    for key in keys {
+                        let item = ItemKey::Key(key_text(key));
+                        let (path1,path2) = (left.path.push(item.clone()),right.path.push(item.clone()));
+                        let left = hash1.get(key).map(|value| Side{value,path: &path1,class: left.class});
+                        let right = hash2.get(key).map(|value| Side{value,path: &path2,class: right.class});
+                        renderer.pair(&format!("{}:",key_text(key)),&path.push(item),left,right,depth)?;
+                    }
+                    Ok(())
+                })
+            }
+            Some((left,right,Yaml::Array(arr1),Yaml::Array(arr2))) if !arr1.is_empty() && !arr2.is_empty() => {
+                let aligned = match self.doc {
+                    Some(doc) => align_arrays(self.opts,self.strategy,doc,path,arr1,arr2)?,
+                    None => Vec::new()
+                };
+                self.container(label,left,right,depth,|renderer,depth| {
+                    for (i,j,item) in aligned {
+                        let path1 = i.map(|i| left.path.push(ItemKey::Index(i))).unwrap_or_default();
+                        let path2 = j.map(|j| right.path.push(ItemKey::Index(j))).unwrap_or_default();
+                        let left = i.map(|i| Side{value: &arr1[i],path: &path1,class: left.class});
+                        let right = j.map(|j| Side{value: &arr2[j],path: &path2,class: right.class});
+                        renderer.pair("-",&path.push(item),left,right,depth)?;
+                    }
+                    Ok(())
+                })
+            }
+            _ => {
+                let (mut lines1,mut lines2) = (Vec::new(),Vec::new());
+                if let Some(left) = left {
+                    Renderer::lines(&self.left,label,left,depth,&mut lines1);
+                }
+                if let Some(right) = right {
+                    Renderer::lines(&self.right,label,right,depth,&mut lines2);
+                }
+                self.rows(&lines1,&lines2)
+            }
+        }
+    }
+}
+
+fn status(document: &DocumentDiffs) -> &'static str {
+    match (document.original,document.modified) {
+        (Some(_),None) => "removed",
+        (None,Some(_)) => "added",
+        _ if document.diffs.is_empty() => "unchanged",
+        _ => "changed"
+    }
+}
+
+fn count(diffs: &Diffs) -> String {
+    let total = DiffStats::from(diffs).total;
+    format!("{} {}",total,if total == 1 {"difference"} else {"differences"})
+}
+
+/** A section showing a pair of documents side by side, or a document only in one file */
+fn document(out: &mut String, comparison: &Comparison, opts: &DiffOptions, strategy: &Option<Strategy>, id: usize, document: &DocumentDiffs) -> Result<()> {
+    let (doc1,doc2) = (document.original.and_then(|key| comparison.document(key,true)),document.modified.and_then(|key| comparison.document(key,false)));
+    let (doc1,doc2) = (doc1.as_deref(),doc2.as_deref());
+    let mut renderer = Renderer{opts,strategy,doc: document.original,left: Highlights::default(),right: Highlights::default(),out: String::new()};
+    for diff in &document.diffs {
+        match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => renderer.right.insert(doc2,&lav.loc.path,"added"),
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => renderer.left.insert(doc1,&lav.loc.path,"removed"),
+            Diff::Differ(lav1,lav2) => {
+                renderer.left.insert(doc1,&lav1.loc.path,"changed");
+                renderer.right.insert(doc2,&lav2.loc.path,"changed");
+            }
+            Diff::Move(lav1,lav2) => {
+                renderer.left.insert(doc1,&lav1.loc.path,"moved");
+                renderer.right.insert(doc2,&lav2.loc.path,"moved");
+            }
+            Diff::DocumentRenamed(_,_) => ()
+        }
+    }
+    let root = KeyPath::new();
+    let (left,right) = (doc1.map(|value| Side{value,path: &root,class: None}),doc2.map(|value| Side{value,path: &root,class: None}));
+    renderer.pair("",&root,left,right,0)?;
+    writeln!(out,"<section id=\"doc-{}\">",id)?;
+    writeln!(out,"<h2>{} <span class=\"count\">({}, {})</span></h2>",escape(&document.to_string()),status(document),count(&document.diffs))?;
+    writeln!(out,"<div class=\"files\"><div>{}</div><div>{}</div></div>",escape(&opts.file1),escape(&opts.file2))?;
+    out.push_str(&renderer.out);
+    writeln!(out,"</section>")?;
+    Ok(())
+}
+
+/**
+ * A self-contained HTML page showing each pair of documents side by side, with the
+ * values that differ highlighted. Values without differences within them are collapsed,
+ * and the page starts with a list of the documents linking to each.
+ */
+pub fn html_report(comparison: &Comparison, opts: &DiffOptions, strategy: &Option<Strategy>, diffs: &Diffs) -> Result<String> {
+    let documents = comparison.document_diffs(diffs);
+    let title = escape(&format!("{} -> {}",opts.file1,opts.file2));
+    let mut out = String::new();
+    writeln!(out,"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>yamldiff: {}</title>",title)?;
+    writeln!(out,"<style>{}</style>\n</head>\n<body>",STYLE)?;
+    writeln!(out,"<h1>{}</h1>\n<p>{}</p>\n<nav>\n<ul>",title,escape(&DiffStats::from(diffs).to_string()))?;
+    for (id,document) in documents.iter().enumerate() {
+        writeln!(out,"<li class=\"{}\"><a href=\"#doc-{}\">{}</a> <span class=\"count\">{}</span></li>",
+            status(document),id,escape(&document.to_string()),count(&document.diffs))?;
+    }
+    writeln!(out,"</ul>\n</nav>")?;
+    for (id,doc) in documents.iter().enumerate() {
+        document(&mut out,comparison,opts,strategy,id,doc)?;
+    }
+    write!(out,"</body>\n</html>")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_html_report() {
+        let y1 = YamlLoader::load_from_str("a: 1\nb: {c: <x>}\nlist: [p, q]\n").unwrap();
+        let y2 = YamlLoader::load_from_str("a: 2\nb: {c: <x>}\nlist: [o, p, q]\n").unwrap();
+        let opts = DiffOptions{lcs: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let comparison = Comparison::new(&opts,&None,y1,y2).unwrap();
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        let html = html_report(&comparison,&opts,&None,&diffs).unwrap();
+        let row = |left: &str, right: &str| format!("<div class=\"row\">{}{}</div>\n",left,right);
+        let section = concat!(
+            "<section id=\"doc-0\">\n",
+            "<h2>[0] <span class=\"count\">(changed, 2 differences)</span></h2>\n",
+            "<div class=\"files\"><div>f1.yaml</div><div>f2.yaml</div></div>\n").to_string()
+            + &row("<div class=\"cell changed\" style=\"--depth:0\">a: 1</div>","<div class=\"cell changed\" style=\"--depth:0\">a: 2</div>")
+            + "<details><summary class=\"row\"><div class=\"cell\" style=\"--depth:0\">b:</div><div class=\"cell\" style=\"--depth:0\">b:</div></summary>\n"
+            + &row("<div class=\"cell\" style=\"--depth:1\">c: &lt;x&gt;</div>","<div class=\"cell\" style=\"--depth:1\">c: &lt;x&gt;</div>")
+            + "</details>\n"
+            + "<details open><summary class=\"row\"><div class=\"cell\" style=\"--depth:0\">list:</div><div class=\"cell\" style=\"--depth:0\">list:</div></summary>\n"
+            + &row("<div class=\"cell missing\"></div>","<div class=\"cell added\" style=\"--depth:1\">- o</div>")
+            + &row("<div class=\"cell\" style=\"--depth:1\">- p</div>","<div class=\"cell\" style=\"--depth:1\">- p</div>")
+            + &row("<div class=\"cell\" style=\"--depth:1\">- q</div>","<div class=\"cell\" style=\"--depth:1\">- q</div>")
+            + "</details>\n</section>\n";
+        assert!(html.contains(&section),"{}",html);
+        assert!(html.contains("<li class=\"changed\"><a href=\"#doc-0\">[0]</a> <span class=\"count\">2 differences</span></li>"));
+        assert!(html.starts_with("<!DOCTYPE html>") && html.ends_with("</html>"));
+    }
+
+    #[test]
+    fn test_html_renamed_count() {
+        let y1 = YamlLoader::load_from_str("apiVersion: v1\nkind: ConfigMap\nmetadata: {name: a}\ndata: {x: '1', y: '2'}\n").unwrap();
+        let y2 = YamlLoader::load_from_str("apiVersion: v1\nkind: ConfigMap\nmetadata: {name: b}\ndata: {x: '1', y: '2'}\n").unwrap();
+        let opts = DiffOptions{k8s: true, similarity: Some(0.5), ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let comparison = Comparison::new(&opts,&None,y1,y2).unwrap();
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        let html = html_report(&comparison,&opts,&None,&diffs).unwrap();
+        assert!(html.contains("<span class=\"count\">(changed, 1 difference)</span>"),"{}",html);
+    }
+}
@@ -0,0 +1,288 @@
+use serde::Serialize;
+use serde_json::{Map,Number,Value};
+use yaml_rust::Yaml;
+use crate::error::Result;
+use crate::keypath::{ItemKey,scalar_string};
+use crate::yamldiff::{Diff,Diffs,DiffStats,DocKey,Location};
+
+/**
+ * Convert a YAML value to its JSON equivalent. Hash keys that aren't strings are
+ * converted to their scalar string form, and aliases or bad values become null.
+ */
+pub fn yaml_to_json(yaml: &Yaml) -> Value {
+    match yaml {
+        Yaml::Real(_) => yaml.as_f64().and_then(Number::from_f64).map(Value::Number).unwrap_or(Value::Null),
+        Yaml::Integer(i) => Value::Number(Number::from(*i)),
+        Yaml::String(s) => Value::String(s.clone()),
+        Yaml::Boolean(b) => Value::Bool(*b),
+        Yaml::Array(arr) => Value::Array(arr.iter().map(yaml_to_json).collect()),
+        Yaml::Hash(hash) => {
+            let mut map = Map::new();
+            for (k,v) in hash {
+                let key = scalar_string(k).unwrap_or_else(|| format!("{:?}",k));
+                map.insert(key,yaml_to_json(v));
+            }
+            Value::Object(map)
+        },
+        Yaml::Null | Yaml::Alias(_) | Yaml::BadValue => Value::Null
+    }
+}
+
+/** A document key, with each part of Kubernetes metadata as a separate field */
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonDocKey<'a> {
+    Position{position: i32},
+    K8S{
+        #[serde(rename="apiVersion")]
+        api_version: &'a str,
+        kind: &'a str,
+        name: &'a str,
+        namespace: Option<&'a str>
+    },
+    Properties{properties: Map<String,Value>}
+}
+
+impl<'a> JsonDocKey<'a> {
+    fn from(key: &'a DocKey) -> JsonDocKey<'a> {
+        match key {
+            DocKey::Position(n) => JsonDocKey::Position{position: *n},
+            DocKey::K8S(m) => JsonDocKey::K8S{
+                api_version: &m.grv.api_version,
+                kind: &m.grv.kind,
+                name: &m.name,
+                namespace: m.namespace.as_deref()
+            },
+            DocKey::Properties(props) => JsonDocKey::Properties{
+                properties: props.iter().map(|(k,v)| (k.clone(),Value::String(v.clone()))).collect()
+            }
+        }
+    }
+}
+
+/** A value as compact JSON, such as in a message */
+pub(crate) fn json_text(value: &Yaml) -> String {
+    serde_json::to_string(&yaml_to_json(value)).unwrap_or_default()
+}
+
+/**
+ * A value as shown in a report. Strings are unquoted unless they would read as a
+ * different value, and anything else is shown as compact JSON.
+ */
+pub(crate) fn scalar_text(value: &Yaml) -> String {
+    match value {
+        Yaml::String(s) if !s.is_empty() && s.trim() == s && Yaml::from_str(s) == *value => s.clone(),
+        _ => json_text(value)
+    }
+}
+
+/** A path component: a hash key as a string, an array index as a number or an element match */
+fn path_component(key: &ItemKey) -> Value {
+    match key {
+        ItemKey::Key(k) => Value::String(k.clone()),
+        ItemKey::Index(i) => Value::Number(Number::from(*i)),
+        ItemKey::Match(k,v) => {
+            let mut map = Map::new();
+            map.insert(k.clone(),Value::String(v.clone()));
+            Value::Object(map)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonLocation<'a> {
+    file: &'a str,
+    document: JsonDocKey<'a>,
+    path: String,
+    components: Vec<Value>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    column: Option<usize>
+}
+
+impl<'a> JsonLocation<'a> {
+    fn from(loc: &'a Location) -> JsonLocation<'a> {
+        JsonLocation{
+            file: &loc.fname,
+            document: JsonDocKey::from(&loc.doc),
+            path: loc.path.to_string(),
+            components: loc.path.0.iter().map(path_component).collect(),
+            line: loc.mark.map(|mark| mark.line),
+            column: loc.mark.map(|mark| mark.col)
+        }
+    }
+}
+
+/**
+ * A single difference. Locations and values are only given for the sides of
+ * the comparison that the difference applies to.
+ */
+#[derive(Serialize)]
+struct JsonDiff<'a> {
+    kind: &'static str,
+    #[serde(skip_serializing_if="Option::is_none")]
+    original: Option<JsonLocation<'a>>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    modified: Option<JsonLocation<'a>>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    old_value: Option<Value>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    new_value: Option<Value>
+}
+
+impl<'a> JsonDiff<'a> {
+    fn from(diff: &'a Diff) -> JsonDiff<'a> {
+        let (kind,original,modified,old_value,new_value) = match diff {
+            Diff::Add(lav) => ("add",None,Some(&lav.loc),None,Some(&lav.value)),
+            Diff::Remove(lav) => ("remove",Some(&lav.loc),None,Some(&lav.value),None),
+            Diff::AddEmpty(lav) => ("add-empty",None,Some(&lav.loc),None,Some(&lav.value)),
+            Diff::RemoveEmpty(lav) => ("remove-empty",Some(&lav.loc),None,Some(&lav.value),None),
+            Diff::Differ(lav1,lav2) => ("change",Some(&lav1.loc),Some(&lav2.loc),Some(&lav1.value),Some(&lav2.value)),
+            Diff::Move(lav1,lav2) => ("move",Some(&lav1.loc),Some(&lav2.loc),Some(&lav1.value),Some(&lav2.value)),
+            Diff::DocumentRenamed(loc1,loc2) => ("rename",Some(loc1),Some(loc2),None,None)
+        };
+        JsonDiff{
+            kind,
+            original: original.map(JsonLocation::from),
+            modified: modified.map(JsonLocation::from),
+            old_value: old_value.map(|v| yaml_to_json(v)),
+            new_value: new_value.map(|v| yaml_to_json(v))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonStats {
+    total: usize,
+    additions: usize,
+    removals: usize,
+    changes: usize,
+    moves: usize,
+    renames: usize,
+    empties: usize
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    #[serde(skip_serializing_if="Option::is_none")]
+    diffs: Option<Vec<JsonDiff<'a>>>,
+    stats: JsonStats
+}
+
+fn report<'d>(diffs: &'d Diffs, stats: &DiffStats, count: bool) -> JsonReport<'d> {
+    JsonReport{
+        diffs: if count { None } else { Some(diffs.iter().map(JsonDiff::from).collect()) },
+        stats: JsonStats{
+            total: stats.total,
+            additions: stats.additions,
+            removals: stats.removals,
+            changes: stats.changes,
+            moves: stats.moves,
+            renames: stats.renames,
+            empties: stats.empties
+        }
+    }
+}
+
+/**
+ * Produce a JSON report of the differences, with one record per difference and the
+ * given totals of each kind. If `count` is true, only the totals are reported.
+ */
+pub fn diffs_to_json(diffs: &Diffs, stats: &DiffStats, count: bool) -> Result<Value> {
+    Ok(serde_json::to_value(report(diffs,stats,count))?)
+}
+
+/** Format the JSON report of the differences as text, with fields in a fixed order */
+pub fn diffs_to_json_string(diffs: &Diffs, stats: &DiffStats, count: bool) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&report(diffs,stats,count))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+    use serde_json::json;
+    use crate::marks::mark_documents;
+    use crate::yamldiff::{diff_docs,Comparison,DiffOptions};
+
+    #[test]
+    fn test_yaml_to_json() {
+        let yaml = &YamlLoader::load_from_str("{a: 1, b: [x, 2.5, true, ~], 3: c}").unwrap()[0];
+        assert_eq!(json!({"a": 1, "b": ["x", 2.5, true, null], "3": "c"}),yaml_to_json(yaml));
+    }
+
+    #[test]
+    fn test_scalar_text() {
+        let text = |yaml: &str| scalar_text(&YamlLoader::load_from_str(yaml).unwrap()[0]);
+        assert_eq!("a b",text("a b"));
+        assert_eq!("\"1\"",text("'1'"));
+        assert_eq!("\"\"",text("''"));
+        assert_eq!("\" x\"",text("' x'"));
+        assert_eq!("1",text("1"));
+        assert_eq!("null",text("~"));
+        assert_eq!("{\"a\":[1]}",text("{a: [1]}"));
+    }
+
+    #[test]
+    fn test_diffs_to_json() {
+        let y1 = YamlLoader::load_from_str("a: 1\nb: [x, y]\n").unwrap();
+        let y2 = YamlLoader::load_from_str("a: 2\nb: [x]\nc: {d: e}\n").unwrap();
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let diffs = diff_docs(&opts,&None,y1,y2).unwrap();
+        let json = diffs_to_json(&diffs,&DiffStats::from(&diffs),false).unwrap();
+        assert_eq!(json!({
+            "diffs": [
+                {
+                    "kind": "change",
+                    "original": {"file": "f1.yaml", "document": {"position": 0}, "path": "a", "components": ["a"]},
+                    "modified": {"file": "f2.yaml", "document": {"position": 0}, "path": "a", "components": ["a"]},
+                    "old_value": 1,
+                    "new_value": 2
+                },
+                {
+                    "kind": "remove",
+                    "original": {"file": "f1.yaml", "document": {"position": 0}, "path": "b[1]", "components": ["b",1]},
+                    "old_value": "y"
+                },
+                {
+                    "kind": "add",
+                    "modified": {"file": "f2.yaml", "document": {"position": 0}, "path": "c.d", "components": ["c","d"]},
+                    "new_value": "e"
+                }
+            ],
+            "stats": {"total": 3, "additions": 1, "removals": 1, "changes": 1, "moves": 0, "renames": 0, "empties": 0}
+        }),json);
+        assert_eq!(None,diffs_to_json(&diffs,&DiffStats::from(&diffs),true).unwrap().get("diffs"));
+    }
+
+    #[test]
+    fn test_k8s_doc_key() {
+        let y1 = YamlLoader::load_from_str("apiVersion: v1\nkind: Service\nmetadata: {name: s, namespace: ns}\nspec: {x: 1}\n").unwrap();
+        let y2 = YamlLoader::load_from_str("apiVersion: v1\nkind: Service\nmetadata: {name: s, namespace: ns}\nspec: {x: 2}\n").unwrap();
+        let opts = DiffOptions{k8s: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let diffs = diff_docs(&opts,&None,y1,y2).unwrap();
+        let json = diffs_to_json(&diffs,&DiffStats::from(&diffs),false).unwrap();
+        assert_eq!(
+            json!({"apiVersion": "v1", "kind": "Service", "name": "s", "namespace": "ns"}),
+            json["diffs"][0]["original"]["document"]
+        );
+        assert_eq!(json!(["spec","x"]),json["diffs"][0]["modified"]["components"]);
+    }
+
+    #[test]
+    fn test_location_marks() {
+        let (text1,text2) = ("a:\n  b: 1\n","a:\n  c: 2\n  b: 3\n");
+        let y1 = YamlLoader::load_from_str(text1).unwrap();
+        let y2 = YamlLoader::load_from_str(text2).unwrap();
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = Comparison::new(&opts,&None,y1,y2).unwrap()
+            .with_marks(mark_documents(text1).unwrap(),mark_documents(text2).unwrap());
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        let json = diffs_to_json(&diffs,&DiffStats::from(&diffs),false).unwrap();
+        assert_eq!(json!({"file": "f1.yaml", "document": {"position": 0}, "path": "a.b", "components": ["a","b"], "line": 2, "column": 3}),
+            json["diffs"][0]["original"]);
+        assert_eq!(json!(3),json["diffs"][0]["modified"]["line"]);
+        assert_eq!(json!(2),json["diffs"][1]["modified"]["line"]);
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use yaml_rust::Yaml;
+use crate::error::{ErrorKind,Result};
+use crate::json::yaml_to_json;
+use crate::keypath::{ItemKey,KeyPath};
+use crate::yamldiff::{Comparison,Diff,Diffs,DocKey};
+
+/**
+ * A JSON Pointer (RFC 6901) to the value at a path, which must have been resolved so
+ * that it has no element match components.
+ */
+fn pointer(path: &KeyPath) -> Result<String> {
+    let mut result = String::new();
+    for item in &path.0 {
+        result.push('/');
+        match item {
+            ItemKey::Key(key) => result.push_str(&key.replace('~',"~0").replace('/',"~1")),
+            ItemKey::Index(index) => result.push_str(&index.to_string()),
+            ItemKey::Match(_,_) => return Err(ErrorKind::KeyNotFound(path.to_string()).into())
+        }
+    }
+    Ok(result)
+}
+
+/** An RFC 6902 operation */
+#[derive(Serialize,Debug)]
+pub struct Operation {
+    pub op: &'static str,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub from: Option<String>,
+    pub path: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub value: Option<Value>
+}
+
+impl Operation {
+    fn new(op: &'static str, path: &KeyPath, value: Option<Value>) -> Result<Operation> {
+        Ok(Operation{op, from: None, path: pointer(path)?, value})
+    }
+    fn moved(from: &KeyPath, path: &KeyPath) -> Result<Operation> {
+        Ok(Operation{op: "move", from: Some(pointer(from)?), path: pointer(path)?, value: None})
+    }
+}
+
+/** The operations that turn one document into another */
+pub type Patch = Vec<Operation>;
+
+/** A value to be removed, or moved out of its original position */
+struct Removal {
+    path: KeyPath,
+    moved: bool
+}
+
+/** A value to be added, or moved into its modified position from the given path */
+struct Addition {
+    path: KeyPath,
+    from: Option<KeyPath>,
+    value: Value
+}
+
+/**
+ * Builds a list of patch operations, keeping track of where moved array elements are.
+ * Elements that have moved are first taken out of place to the front of their array,
+ * so the indices of the other elements are offset by the number of elements parked
+ * there, and then moved to their modified position.
+ */
+#[derive(Default)]
+struct PatchBuilder {
+    ops: Patch,
+    /** Original indices of the elements parked at the front of each array, by the array's original path */
+    parked: HashMap<KeyPath,Vec<usize>>,
+    /** Original path of each array with moved elements, by its modified path */
+    moved_arrays: HashMap<KeyPath,KeyPath>
+}
+
+impl PatchBuilder {
+    fn parked_count(&self, array: &KeyPath) -> usize {
+        self.parked.get(array).map(Vec::len).unwrap_or(0)
+    }
+
+    /** The current position of a value, given its path in the original document */
+    fn original_position(&self, path: &KeyPath) -> KeyPath {
+        let items = path.0.iter().enumerate().map(|(depth,item)| match item {
+            ItemKey::Index(i) => ItemKey::Index(i + self.parked_count(&path.truncated(depth))),
+            _ => item.clone()
+        });
+        KeyPath(items.collect())
+    }
+
+    /** The current position of a value, given its path in the modified document */
+    fn modified_position(&self, path: &KeyPath) -> KeyPath {
+        let items = path.0.iter().enumerate().map(|(depth,item)| match item {
+            ItemKey::Index(j) => {
+                let parked = self.moved_arrays.get(&path.truncated(depth)).map(|array| self.parked_count(array));
+                ItemKey::Index(j + parked.unwrap_or(0))
+            },
+            _ => item.clone()
+        });
+        KeyPath(items.collect())
+    }
+
+    fn push_move(&mut self, from: &KeyPath, to: &KeyPath) -> Result<()> {
+        if from != to {
+            self.ops.push(Operation::moved(from,to)?);
+        }
+        Ok(())
+    }
+
+    fn replace(&mut self, path: &KeyPath, value: Value) -> Result<()> {
+        self.ops.push(Operation::new("replace",path,Some(value))?);
+        Ok(())
+    }
+
+    fn remove(&mut self, removal: Removal) -> Result<()> {
+        let from = self.original_position(&removal.path);
+        if removal.moved {
+            let array = removal.path.truncated(removal.path.0.len()-1);
+            let to = self.original_position(&array).push(ItemKey::Index(0));
+            self.push_move(&from,&to)?;
+            if let Some(ItemKey::Index(i)) = removal.path.0.last() {
+                self.parked.entry(array).or_default().insert(0,*i);
+            }
+        } else {
+            self.ops.push(Operation::new("remove",&from,None)?);
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, addition: Addition) -> Result<()> {
+        match addition.from {
+            Some(from) => {
+                let array = from.truncated(from.0.len()-1);
+                let target_array = addition.path.truncated(addition.path.0.len()-1);
+                let current_array = self.modified_position(&target_array);
+                let parked = self.parked.entry(array).or_default();
+                let position = match from.0.last() {
+                    Some(ItemKey::Index(i)) => parked.iter().position(|p| p == i),
+                    _ => None
+                };
+                if let Some(q) = position {
+                    parked.remove(q);
+                    let from = current_array.push(ItemKey::Index(q));
+                    let to = self.modified_position(&addition.path);
+                    self.push_move(&from,&to)?;
+                }
+            }
+            None => {
+                let to = self.modified_position(&addition.path);
+                self.ops.push(Operation::new("add",&to,Some(addition.value))?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The JSON Patch operations that turn the original document into the modified one, from
+ * the differences found between them. Values are replaced first, then removed or moved
+ * out of place in descending path order, so that the paths still to be removed are
+ * unaffected, and finally added or moved into place in ascending path order.
+ */
+fn json_patch(doc1: &Yaml, doc2: &Yaml, key1: &DocKey, key2: &DocKey, diffs: &Diffs) -> Result<Patch> {
+    let mut builder = PatchBuilder::default();
+    let mut removals = Vec::new();
+    let mut additions = Vec::new();
+    for diff in diffs.iter().filter(|diff| diff.is_between(key1,key2)) {
+        match diff {
+            Diff::Differ(lav1,lav2) => {
+                builder.replace(&lav1.loc.path.resolved(doc1)?,yaml_to_json(&lav2.value))?;
+            }
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => {
+                removals.push(Removal{path: lav.loc.path.resolved(doc1)?, moved: false});
+            }
+            Diff::Add(lav) | Diff::AddEmpty(lav) => {
+                additions.push(Addition{path: lav.loc.path.resolved(doc2)?, from: None, value: yaml_to_json(&lav.value)});
+            }
+            Diff::Move(lav1,lav2) => {
+                let from = lav1.loc.path.resolved(doc1)?;
+                let to = lav2.loc.path.resolved(doc2)?;
+                builder.moved_arrays.insert(to.truncated(to.0.len()-1),from.truncated(from.0.len()-1));
+                removals.push(Removal{path: from.clone(), moved: true});
+                additions.push(Addition{path: to, from: Some(from), value: Value::Null});
+            }
+            Diff::DocumentRenamed(_,_) => ()
+        }
+    }
+    removals.sort_by(|r1,r2| r2.path.cmp(&r1.path));
+    for removal in removals {
+        builder.remove(removal)?;
+    }
+    additions.sort_by(|a1,a2| a1.path.cmp(&a2.path));
+    for addition in additions {
+        builder.add(addition)?;
+    }
+    Ok(builder.ops)
+}
+
+/**
+ * An RFC 6902 JSON Patch for each pair of documents compared that have differences,
+ * in the order of the original file. The differences should have been found with
+ * the `subtrees` option, so that values present on only one side are added or removed
+ * as a whole.
+ */
+pub fn json_patches(comparison: &Comparison, diffs: &Diffs) -> Result<Vec<Patch>> {
+    let mut result = Vec::new();
+    for (key1,key2) in comparison.document_pairs() {
+        let patch = json_patch(&comparison.original[key1],&comparison.modified[key2],key1,key2,diffs)?;
+        if !patch.is_empty() {
+            result.push(patch);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+    use crate::yamldiff::DiffOptions;
+    use serde_json::json;
+    use crate::strategy::Strategy;
+
+    fn patches(opts: &DiffOptions, strategy: &Option<Strategy>, original: &str, modified: &str) -> Vec<Value> {
+        let y1 = YamlLoader::load_from_str(original).unwrap();
+        let y2 = YamlLoader::load_from_str(modified).unwrap();
+        let comparison = Comparison::new(opts,strategy,y1,y2).unwrap();
+        let diffs = comparison.diffs(opts,strategy).unwrap();
+        let patches = json_patches(&comparison,&diffs).unwrap();
+        patches.iter().map(|patch| serde_json::to_value(patch).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_pointer() {
+        assert_eq!("",pointer(&KeyPath::new()).unwrap());
+        assert_eq!("/a~1b/c~0d/0",pointer(&KeyPath(vec![ItemKey::from("a/b"),ItemKey::from("c~d"),ItemKey::from(0)])).unwrap());
+        assert!(pointer(&KeyPath(vec![ItemKey::from("items"),ItemKey::Match("name".to_string(),"a".to_string())])).is_err());
+    }
+
+    #[test]
+    fn test_hash_patch() {
+        let opts = DiffOptions{subtrees: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let result = patches(&opts,&None,
+            "a: 1\nb: {c: 2, d: 3}\nlist: [x, y, z]\n",
+            "a: 2\nb: {c: 2}\ne: {f: [g]}\nlist: [x]\n---\n{}\n");
+        assert_eq!(vec![json!([
+            {"op": "replace", "path": "/a", "value": 2},
+            {"op": "remove", "path": "/list/2"},
+            {"op": "remove", "path": "/list/1"},
+            {"op": "remove", "path": "/b/d"},
+            {"op": "add", "path": "/e", "value": {"f": ["g"]}}
+        ])],result);
+    }
+
+    #[test]
+    fn test_keyed_array_moves() {
+        let strategy = Some(Strategy::from_str("arrays:\n  - path: items\n    key: name\n").unwrap());
+        let opts = DiffOptions{subtrees: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let result = patches(&opts,&strategy,
+            "items: [{name: a}, {name: b, v: 1}, {name: c}, {name: d}]\n",
+            "items: [{name: d}, {name: e}, {name: a}, {name: b, v: 2}]\n");
+        assert_eq!(vec![json!([
+            {"op": "replace", "path": "/items/1/v", "value": 2},
+            {"op": "move", "from": "/items/3", "path": "/items/0"},
+            {"op": "remove", "path": "/items/3"},
+            {"op": "add", "path": "/items/1", "value": {"name": "e"}}
+        ])],result);
+    }
+
+    #[test]
+    fn test_lcs_array_moves() {
+        let opts = DiffOptions{subtrees: true, lcs: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let result = patches(&opts,&None,"[a, b, c, d, e]\n","[e, a, b, x, c, d]\n");
+        assert_eq!(vec![json!([
+            {"op": "move", "from": "/4", "path": "/0"},
+            {"op": "add", "path": "/3", "value": "x"}
+        ])],result);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt::Write;
+use crate::error::Result;
+use crate::yamldiff::{write_diffs,Comparison,Diffs,DiffOptions,DiffStats};
+
+/**
+ * Escape text for XML content or attribute values. Characters that XML 1.0 can't
+ * represent at all are replaced.
+ */
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            '\t' | '\n' | '\r' => result.push(ch),
+            ch if ch < ' ' || ch == '\u{fffe}' || ch == '\u{ffff}' => result.push('\u{fffd}'),
+            ch => result.push(ch)
+        }
+    }
+    result
+}
+
+/**
+ * A JUnit XML report with a test case for each document compared, which fails if any
+ * differences were found in it. The failure gives the differences as text.
+ */
+pub fn junit_report(comparison: &Comparison, opts: &DiffOptions, diffs: &Diffs) -> Result<String> {
+    let cases = comparison.document_diffs(diffs);
+    let failures = cases.iter().filter(|case| !case.diffs.is_empty()).count();
+    let suite = format!("{} -> {}",opts.file1,opts.file2);
+    let mut out = String::new();
+    writeln!(out,"<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(out,"<testsuites name=\"yamldiff\" tests=\"{}\" failures=\"{}\">",cases.len(),failures)?;
+    writeln!(out,"  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">",escape(&suite),cases.len(),failures)?;
+    for case in &cases {
+        let attributes = format!("name=\"{}\" classname=\"{}\"",escape(&case.to_string()),escape(&opts.file2));
+        if case.diffs.is_empty() {
+            writeln!(out,"    <testcase {}/>",attributes)?;
+        } else {
+            let mut text = Vec::new();
+            write_diffs(&mut text,false,&case.diffs)?;
+            let text = String::from_utf8_lossy(&text);
+            writeln!(out,"    <testcase {}>",attributes)?;
+            writeln!(out,"      <failure message=\"{}\" type=\"difference\">{}</failure>",
+                escape(&DiffStats::from(&case.diffs).to_string()),escape(text.trim_start_matches('\n')))?;
+            writeln!(out,"    </testcase>")?;
+        }
+    }
+    writeln!(out,"  </testsuite>")?;
+    write!(out,"</testsuites>")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a &lt;b&gt; &amp; &quot;c&quot; &apos;d&apos;\n\u{fffd}",escape("a <b> & \"c\" 'd'\n\u{1b}"));
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let y1 = YamlLoader::load_from_str("a: 1\n---\nb: <1>\n---\nc: 1\n").unwrap();
+        let y2 = YamlLoader::load_from_str("a: 1\n---\nb: <2>\n").unwrap();
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = Comparison::new(&opts,&None,y1,y2).unwrap();
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        assert_eq!(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<testsuites name=\"yamldiff\" tests=\"3\" failures=\"2\">\n",
+            "  <testsuite name=\"f1.yaml -&gt; f2.yaml\" tests=\"3\" failures=\"2\">\n",
+            "    <testcase name=\"[0]\" classname=\"f2.yaml\"/>\n",
+            "    <testcase name=\"[1]\" classname=\"f2.yaml\">\n",
+            "      <failure message=\"1 difference (changes: 1)\" type=\"difference\">",
+            "&lt; f1.yaml: [1] b = String(&quot;&lt;1&gt;&quot;)\n&gt; f2.yaml: [1] b = String(&quot;&lt;2&gt;&quot;)\n</failure>\n",
+            "    </testcase>\n",
+            "    <testcase name=\"[2]\" classname=\"f2.yaml\">\n",
+            "      <failure message=\"1 difference (removals: 1)\" type=\"difference\">&lt; f1.yaml: [2] c = Integer(1)\n</failure>\n",
+            "    </testcase>\n",
+            "  </testsuite>\n",
+            "</testsuites>"),junit_report(&comparison,&opts,&diffs).unwrap());
+    }
+}
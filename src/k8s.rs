@@ -0,0 +1,91 @@
+use crate::keypath::KeyPath;
+
+/** Path of the pod spec within each kind of workload resource */
+const POD_SPEC_PATHS: &[(&str,&str)] = &[
+    ("Pod", "spec"),
+    ("PodTemplate", "template.spec"),
+    ("Deployment", "spec.template.spec"),
+    ("ReplicaSet", "spec.template.spec"),
+    ("ReplicationController", "spec.template.spec"),
+    ("StatefulSet", "spec.template.spec"),
+    ("DaemonSet", "spec.template.spec"),
+    ("Job", "spec.template.spec"),
+    ("CronJob", "spec.jobTemplate.spec.template.spec")
+];
+
+/** Merge keys of arrays in a pod spec, by path relative to the pod spec */
+const POD_SPEC_MERGE_KEYS: &[(&str,&str)] = &[
+    ("containers", "name"),
+    ("initContainers", "name"),
+    ("ephemeralContainers", "name"),
+    ("volumes", "name"),
+    ("tolerations", "key"),
+    ("imagePullSecrets", "name"),
+    ("hostAliases", "ip"),
+    ("topologySpreadConstraints", "topologyKey"),
+    ("resourceClaims", "name")
+];
+
+/** Arrays of containers within a pod spec */
+const CONTAINER_ARRAYS: &[&str] = &["containers", "initContainers", "ephemeralContainers"];
+
+/** Merge keys of arrays in a container, by path relative to the container */
+const CONTAINER_MERGE_KEYS: &[(&str,&str)] = &[
+    ("env", "name"),
+    ("ports", "containerPort"),
+    ("volumeMounts", "mountPath"),
+    ("volumeDevices", "devicePath"),
+    ("resizePolicy", "resourceName")
+];
+
+/** Merge keys of arrays in other kinds of resource, by kind and path */
+const RESOURCE_MERGE_KEYS: &[(&str,&str,&str)] = &[
+    ("Service", "spec.ports", "port")
+];
+
+fn pod_spec_merge_key(relative: &str) -> Option<&'static str> {
+    if let Some((_,key)) = POD_SPEC_MERGE_KEYS.iter().find(|(path,_)| *path == relative) {
+        return Some(key)
+    }
+    let (containers,container_path) = relative.split_once('.')?;
+    if !CONTAINER_ARRAYS.contains(&containers) {
+        return None
+    }
+    CONTAINER_MERGE_KEYS.iter().find(|(path,_)| *path == container_path).map(|(_,key)| *key)
+}
+
+/**
+ * The Kubernetes strategic merge key for the array at the given path in a resource
+ * of the given kind, if there is one. Array indices and matched elements in the
+ * path are ignored.
+ */
+pub fn merge_key(kind: &str, path: &KeyPath) -> Option<&'static str> {
+    let pathstr = path.unindexed().to_string();
+    if let Some((_,_,key)) = RESOURCE_MERGE_KEYS.iter().find(|(k,p,_)| *k == kind && *p == pathstr) {
+        return Some(key)
+    }
+    let (_,pod_spec) = POD_SPEC_PATHS.iter().find(|(k,_)| *k == kind)?;
+    let relative = pathstr.strip_prefix(pod_spec)?.strip_prefix('.')?;
+    pod_spec_merge_key(relative)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge_keys() {
+        let containers = KeyPath::parse("spec.template.spec.containers");
+        assert_eq!(Some("name"),merge_key("Deployment",&containers));
+        assert_eq!(None,merge_key("Pod",&containers));
+        assert_eq!(None,merge_key("ConfigMap",&containers));
+        let env = KeyPath::parse("spec.containers[name=app].env");
+        assert_eq!(Some("name"),merge_key("Pod",&env));
+        let ports = KeyPath::parse("spec.jobTemplate.spec.template.spec.initContainers[0].ports");
+        assert_eq!(Some("containerPort"),merge_key("CronJob",&ports));
+        let service_ports = KeyPath::parse("spec.ports");
+        assert_eq!(Some("port"),merge_key("Service",&service_ports));
+        let args = KeyPath::parse("spec.template.spec.containers[name=app].args");
+        assert_eq!(None,merge_key("StatefulSet",&args));
+    }
+}
@@ -5,13 +5,15 @@ use yaml_rust::yaml;
 use crate::error::{Result,ErrorKind};
 
 /**
- * Component of a path in the document heirarchy. Either an array index
- * or a hash key.
+ * Component of a path in the document heirarchy. Either an array index,
+ * a hash key, or an array element identified by the value of one of its 
+ * keys.
  */
-#[derive(PartialEq,Clone,Debug)]
+#[derive(PartialEq,Eq,PartialOrd,Ord,Hash,Clone,Debug)]
 pub enum ItemKey {
     Index(usize),
-    Key(String)
+    Key(String),
+    Match(String,String)
 }
 
 impl From<&str> for ItemKey {
@@ -29,7 +31,7 @@ impl From<usize> for ItemKey {
 /**
  * A path in the document heirarchy as a vector of path components.
  */
- #[derive(PartialEq,Clone,Debug)]
+ #[derive(PartialEq,Eq,PartialOrd,Ord,Hash,Clone,Debug,Default)]
  pub struct KeyPath(pub Vec<ItemKey>);
  
  struct ParseContext<'a> {
@@ -89,19 +91,18 @@ impl From<usize> for ItemKey {
     fn complete_item(&mut self) {
         if !self.item.is_empty() {
             let start = self.path.0.len();
-            loop {
-                match self.pop_brace() {
-                    Some(brace) => {
-                        if brace.bstart + brace.bstr.len() == self.item.len() {
-                            let rindex = brace.bstr.parse::<usize>();
-                            if let Ok(index) = rindex {
-                                // Array index syntax
-                                self.path.0.push(ItemKey::from(index));
-                                self.item.truncate(self.item.len() - brace.bstr.len())
-                            }
-                        }
-                    },
-                    None => break
+            while let Some(brace) = self.pop_brace() {
+                if brace.bstart + brace.bstr.len() == self.item.len() {
+                    let rindex = brace.bstr.parse::<usize>();
+                    if let Ok(index) = rindex {
+                        // Array index syntax
+                        self.path.0.push(ItemKey::from(index));
+                        self.item.truncate(self.item.len() - brace.bstr.len())
+                    } else if let (true,Some((key,value))) = (brace.bstart > 0, brace.bstr.split_once('=')) {
+                        // Array element match syntax
+                        self.path.0.push(ItemKey::Match(key.to_string(),value.to_string()));
+                        self.item.truncate(self.item.len() - brace.bstr.len())
+                    }
                 }
             }
             if !self.item.is_empty() {
@@ -123,12 +124,37 @@ impl From<usize> for ItemKey {
          newvec.push(key);
          KeyPath(newvec)
      }
-     #[allow(unused)]
+     /** This path with array index and element match components removed */
+     pub fn unindexed(&self) -> KeyPath {
+         let keys = self.0.iter().filter(|item| matches!(item,ItemKey::Key(_))).cloned().collect();
+         KeyPath(keys)
+     }
      pub fn truncated(&self,len: usize) -> KeyPath {
          let mut newvec = self.0.clone();
          newvec.truncate(len);
          KeyPath(newvec)
      }
+     /** This path with element match components replaced by the index of the matching element in the document */
+     pub fn resolved(&self,yaml: &Yaml) -> Result<KeyPath> {
+         let mut result = KeyPath::new();
+         let mut current = yaml;
+         for item in &self.0 {
+             let item = match item {
+                 ItemKey::Match(key,value) => match match_index(current,key,value) {
+                     Some(index) => ItemKey::Index(index),
+                     None => return Err(ErrorKind::KeyNotFound(result.push(item.clone()).to_string()).into())
+                 },
+                 _ => item.clone()
+             };
+             current = match &item {
+                 ItemKey::Key(key) => &current[key.as_str()],
+                 ItemKey::Index(index) => &current[*index],
+                 ItemKey::Match(_,_) => unreachable!()
+             };
+             result.0.push(item);
+         }
+         Ok(result)
+     }
      pub fn parse(pathstr: &str) -> KeyPath {
         let mut ctx = ParseContext::new(pathstr);
         for (i,ch) in pathstr.chars().enumerate() {
@@ -169,6 +195,7 @@ impl From<usize> for ItemKey {
          for item in &self.0 {
              match item {
                  ItemKey::Index(u) => { write!(f,"[{}]",u)?; }
+                 ItemKey::Match(key,value) => { write!(f,"[{}={}]",key,value)?; }
                  ItemKey::Key(str) => {
                      let sep = if first {""} else {"."};
                      if str.contains(".") {
@@ -184,9 +211,61 @@ impl From<usize> for ItemKey {
      }
  }
 
+ /** 
+  * The string form of a scalar YAML value, or None if it is not a scalar 
+  */
+ pub fn scalar_string(y: &Yaml) -> Option<String> {
+    match y {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None
+    }
+ }
+
+ /** Index of the element in an array having a key with the given value */
+ fn match_index(y: &Yaml, key: &str, value: &str) -> Option<usize> {
+    y.as_vec()?.iter().position(|item| scalar_string(&item[key]).as_deref() == Some(value))
+ }
+
  pub trait KeyPathFuncs: std::marker::Sized {
     fn set_at_path<T: Into<KeyPath>>(&mut self,path: T, value: Self) -> Result<()>;
     fn get_at_path<T: Into<KeyPath>>(&self,path: T) -> Result<&Self>;
+    /** Remove the value at the path, returning it. Later elements of an array move down. */
+    fn remove_at_path<T: Into<KeyPath>>(&mut self,path: T) -> Result<Self>;
+    /** Insert a value at the path. Later elements of an array move up, and a hash value is replaced. */
+    fn insert_at_path<T: Into<KeyPath>>(&mut self,path: T, value: Self) -> Result<()>;
+ }
+
+ /** Set the value of a hash key, keeping its position if it already exists */
+ fn replace_or_insert(h: &mut yaml::Hash, key: Yaml, value: Yaml) {
+    match h.get_mut(&key) {
+        Some(existing) => *existing = value,
+        None => { h.insert(key,value); }
+    }
+ }
+
+ /** The container holding the last component of a path, and that component with any element match resolved */
+ fn parent_mut<'y>(y: &'y mut Yaml, path: &KeyPath) -> Result<(&'y mut Yaml,ItemKey)> {
+    let resolved = path.truncated(path.0.len().saturating_sub(1)).resolved(y)?;
+    let mut current = y;
+    for (depth,item) in resolved.0.iter().enumerate() {
+        let next = match (current,item) {
+            (Yaml::Hash(h),ItemKey::Key(key)) => h.get_mut(&Yaml::String(key.clone())),
+            (Yaml::Array(a),ItemKey::Index(index)) => a.get_mut(*index),
+            _ => None
+        };
+        current = next.ok_or_else(|| ErrorKind::KeyNotFound(resolved.truncated(depth+1).to_string()))?;
+    }
+    let last = match path.0.last() {
+        Some(ItemKey::Match(key,value)) => match match_index(current,key,value) {
+            Some(index) => ItemKey::Index(index),
+            None => return Err(ErrorKind::KeyNotFound(path.to_string()).into())
+        },
+        Some(item) => item.clone(),
+        None => return Err(ErrorKind::KeyNotFound(path.to_string()).into())
+    };
+    Ok((current,last))
  }
 
  impl KeyPathFuncs for Yaml {
@@ -202,7 +281,7 @@ impl From<usize> for ItemKey {
                     if let Yaml::Hash(h) = current {
                         match iter.peek() {
                             None => { 
-                                h.insert(Yaml::String(key),value);
+                                replace_or_insert(h,Yaml::String(key),value);
                                 break; 
                             }
                             Some(next_item) => {
@@ -210,7 +289,7 @@ impl From<usize> for ItemKey {
                                 if !h.contains_key(&ykey) {
                                     match next_item {
                                         ItemKey::Key(_) => { h.insert(ykey.clone(),Yaml::Hash(yaml::Hash::new())); }
-                                        ItemKey::Index(_) => { h.insert(ykey.clone(),Yaml::Array(yaml::Array::new())); }
+                                        ItemKey::Index(_) | ItemKey::Match(_,_) => { h.insert(ykey.clone(),Yaml::Array(yaml::Array::new())); }
                                     }
                                 }
                                 current = &mut h[&ykey];
@@ -220,6 +299,23 @@ impl From<usize> for ItemKey {
                         return Err(ErrorKind::WrongType(processing.to_string()).into());
                     }
                 }
+                ItemKey::Match(mkey,mvalue) => {
+                    let found = match_index(current,&mkey,&mvalue);
+                    match (current,found) {
+                        (Yaml::Array(a),Some(index)) => {
+                            match iter.peek() {
+                                None => {
+                                    a[index] = value;
+                                    break;
+                                }
+                                Some(_) => {
+                                    current = &mut a[index]
+                                }
+                            }
+                        }
+                        _ => return Err(ErrorKind::KeyNotFound(processing.push(item_copy).to_string()).into())
+                    }
+                }
                 ItemKey::Index(index) => {
                     if let Yaml::Array(a) = current { 
                         if index >= a.len() {
@@ -244,6 +340,28 @@ impl From<usize> for ItemKey {
         Ok(())
     }
 
+    fn remove_at_path<T: Into<KeyPath>>(&mut self, path: T) -> Result<Yaml> {
+        let path = path.into();
+        let removed = match parent_mut(self,&path)? {
+            (Yaml::Hash(h),ItemKey::Key(key)) => h.remove(&Yaml::String(key)),
+            (Yaml::Array(a),ItemKey::Index(index)) if index < a.len() => Some(a.remove(index)),
+            (Yaml::Array(_),ItemKey::Index(index)) => return Err(ErrorKind::InvalidArrayIndex(index,path.to_string()).into()),
+            _ => return Err(ErrorKind::WrongType(path.to_string()).into())
+        };
+        removed.ok_or_else(|| ErrorKind::KeyNotFound(path.to_string()).into())
+    }
+
+    fn insert_at_path<T: Into<KeyPath>>(&mut self, path: T, value: Yaml) -> Result<()> {
+        let path = path.into();
+        match parent_mut(self,&path)? {
+            (Yaml::Hash(h),ItemKey::Key(key)) => replace_or_insert(h,Yaml::String(key),value),
+            (Yaml::Array(a),ItemKey::Index(index)) if index <= a.len() => a.insert(index,value),
+            (Yaml::Array(_),ItemKey::Index(index)) => return Err(ErrorKind::InvalidArrayIndex(index,path.to_string()).into()),
+            _ => return Err(ErrorKind::WrongType(path.to_string()).into())
+        }
+        Ok(())
+    }
+
     fn get_at_path<T: Into<KeyPath>>(&self, path: T) -> Result<&Yaml> {
         let path = path.into();
         let mut processed_path = KeyPath::new();
@@ -253,6 +371,12 @@ impl From<usize> for ItemKey {
             match item {
                 ItemKey::Key(key) => { result = &result[key.as_str()]; }
                 ItemKey::Index(index) => { result = &result[index]; }
+                ItemKey::Match(key,value) => {
+                    result = match match_index(result,&key,&value) {
+                        Some(index) => &result[index],
+                        None => return Err(ErrorKind::KeyNotFound(processed_path.to_string()).into())
+                    }
+                }
             }
             if result.is_badvalue() {
                 return Err(ErrorKind::KeyNotFound(processed_path.to_string()).into())
@@ -316,6 +440,59 @@ impl From<usize> for ItemKey {
         assert_eq!(KeyPath::from(expected),kp);
     }
 
+    #[test]
+    fn test_parse_match_key() {
+        let kp = KeyPath::parse("spec.containers[name=app].image");
+        let expected: &[ItemKey] = &[ItemKey::from("spec"),ItemKey::from("containers"),ItemKey::Match("name".to_string(),"app".to_string()),ItemKey::from("image")];
+        assert_eq!(KeyPath::from(expected),kp);
+        assert_eq!("spec.containers[name=app].image",kp.to_string());
+        assert_eq!("spec.containers.image",kp.unindexed().to_string());
+    }
+
+    #[test]
+    fn test_remove_insert_value() {
+        let mut y = YamlLoader::load_from_str("a: {b: 1, c: [x, y, z]}\nlist: [{name: n, v: 1}]").unwrap();
+        assert_eq!(Yaml::String("y".to_string()),y[0].remove_at_path("a.c[1]").unwrap());
+        assert_eq!(Yaml::Integer(1),y[0].remove_at_path("a.b").unwrap());
+        y[0].insert_at_path("a.c[0]",Yaml::String("w".to_string())).unwrap();
+        y[0].insert_at_path("a.c[3]",Yaml::String("end".to_string())).unwrap();
+        y[0].insert_at_path("list[name=n].v",Yaml::Integer(2)).unwrap();
+        let expected = YamlLoader::load_from_str("a: {c: [w, x, z, end]}\nlist: [{name: n, v: 2}]").unwrap();
+        assert_eq!(expected,y);
+        assert!(y[0].remove_at_path("a.b").is_err());
+        assert!(y[0].remove_at_path("a.c[4]").is_err());
+        assert!(y[0].insert_at_path("a.d.e",Yaml::Null).is_err());
+        assert!(y[0].insert_at_path("a.c[9]",Yaml::Null).is_err());
+    }
+
+    #[test]
+    fn test_resolve_match_key() {
+        let y = YamlLoader::load_from_str("containers: [{name: app, ports: [{port: 80}, {port: 443}]}]").unwrap();
+        let path = KeyPath::parse("containers[name=app].ports[port=443]");
+        assert_eq!(KeyPath::parse("containers[0].ports[1]"),path.resolved(&y[0]).unwrap());
+        assert!(KeyPath::parse("containers[name=other]").resolved(&y[0]).is_err());
+    }
+
+    #[test]
+    fn test_get_set_match_value() {
+        let yaml = r#"
+        containers:
+            - name: app
+              image: app:1
+            - name: sidecar
+              image: sidecar:1
+        "#;
+        let mut y = YamlLoader::load_from_str(yaml).unwrap();
+        assert_eq!(Yaml::String("sidecar:1".to_string()),*y[0].get_at_path("containers[name=sidecar].image").unwrap());
+        y[0].set_at_path("containers[name=sidecar].image",Yaml::String("sidecar:2".to_string())).unwrap();
+        assert_eq!(y[0]["containers"][1]["image"],Yaml::String("sidecar:2".to_string()));
+        let result = y[0].get_at_path("containers[name=other].image");
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!("key 'containers[name=other]' not found in YAML document, or is wrong type",e.to_string());
+        }
+    }
+
     #[test]
     fn test_set_value() {
         let yaml = r#"
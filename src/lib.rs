@@ -0,0 +1,36 @@
+//! Structural comparison of YAML files.
+//!
+//! Documents are loaded with [`load_file`] (or any `yaml_rust` loader), compared with
+//! [`diff_docs`] according to a set of [`DiffOptions`] and an optional [`Strategy`], and
+//! the differences returned as a list of [`Diff`] values.
+
+#[macro_use]
+extern crate error_chain;
+
+pub mod annotations;
+pub mod context;
+pub mod error;
+pub mod html;
+pub mod json;
+pub mod jsonpatch;
+pub mod junit;
+pub mod k8s;
+pub mod keypath;
+pub mod markdown;
+pub mod marks;
+pub mod merge;
+pub mod mergepatch;
+pub mod patch;
+pub mod sidebyside;
+pub mod similarity;
+pub mod strategy;
+pub mod tree;
+pub mod yamldiff;
+
+pub use crate::error::{Error,ErrorKind,Result};
+pub use crate::keypath::{ItemKey,KeyPath,KeyPathFuncs};
+pub use crate::strategy::Strategy;
+pub use crate::yamldiff::{
+    diff_docs,find_diffs,Comparison,index,load_file,
+    Diff,Diffs,DiffStats,DiffOptions,DocKey,Documents,FileName,K8SMeta,GVK,Location,LocationAndValue
+};
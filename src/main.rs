@@ -1,25 +1,19 @@
-mod yamldiff;
-mod keypath;
-mod error;
-mod strategy;
-#[macro_use]
-extern crate error_chain;
+mod cli;
 
 use clap::Parser;
-use yamldiff::{Opts,do_diff};
+use cli::{Opts,run};
 use error_chain::ChainedError;
 use std::process::exit;
 
 
 fn main() {
     let opts: Opts = Opts::parse();
-    let result = do_diff(&opts);
+    let result = run(&opts);
     match result {
         Ok(n) => exit(n),
         Err(e) => {
-            eprintln!("yamldiff: {}",e.display_chain().to_string());
+            eprintln!("yamldiff: {}",e.display_chain());
             exit(2)
         }
     }
 }
-
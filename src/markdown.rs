@@ -0,0 +1,160 @@
+use std::fmt::Write;
+use diffy::{create_patch,Line,PatchFormatter};
+use yaml_rust::Yaml;
+use crate::error::Result;
+use crate::json::scalar_text;
+use crate::keypath::KeyPath;
+use crate::yamldiff::{Comparison,Diff,Diffs,DiffOptions,DiffStats,DocumentDiffs};
+
+/** Inline code, with a fence long enough not to be closed by any backticks in the text */
+fn code(text: &str) -> String {
+    let mut fence = "`".to_string();
+    while text.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    let pad = if text.starts_with('`') || text.ends_with('`') { " " } else { "" };
+    format!("{}{}{}{}{}",fence,pad,text,pad,fence)
+}
+
+/** A table cell showing a value as it's shown in other reports */
+fn value_cell(value: &Yaml) -> String {
+    code(&scalar_text(value)).replace('|',"\\|")
+}
+
+fn path_cell(path: &KeyPath) -> String {
+    code(&path.to_string()).replace('|',"\\|")
+}
+
+fn multiline(value: &Yaml) -> Option<&str> {
+    value.as_str().filter(|s| s.contains('\n'))
+}
+
+/** The lines of a value changed to or from a multi-line string */
+fn lines(value: &Yaml) -> String {
+    multiline(value).map(str::to_string).unwrap_or_else(|| scalar_text(value))
+}
+
+/** A multi-line string difference, collapsed into a patch of the lines that changed */
+fn details(out: &mut String, path: &KeyPath, old: &str, new: &str) -> Result<()> {
+    let patch = create_patch(old,new);
+    let changed = patch.hunks().iter().flat_map(|hunk| hunk.lines()).filter(|line| !matches!(line,Line::Context(_))).count();
+    let patch = PatchFormatter::new().fmt_patch(&patch).to_string();
+    let mut fence = "```".to_string();
+    while patch.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    let path = path.to_string().replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;");
+    writeln!(out,"<details>\n<summary><code>{}</code> ({} {} changed)</summary>\n",path,changed,if changed == 1 {"line"} else {"lines"})?;
+    writeln!(out,"{}diff\n{}{}\n</details>\n",fence,patch,fence)?;
+    Ok(())
+}
+
+/** The differences in a document, as a table of paths and values followed by any multi-line string patches */
+fn document(out: &mut String, document: &DocumentDiffs) -> Result<()> {
+    let status = match (document.original,document.modified) {
+        (Some(_),None) => " (removed)",
+        (None,Some(_)) => " (added)",
+        _ => ""
+    };
+    writeln!(out,"### {}{}\n",code(&document.to_string()),status)?;
+    let mut rows = Vec::new();
+    let mut patches = String::new();
+    for diff in &document.diffs {
+        match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => match multiline(&lav.value) {
+                Some(text) => details(&mut patches,&lav.loc.path,"",text)?,
+                None => rows.push((path_cell(&lav.loc.path),String::new(),value_cell(&lav.value)))
+            },
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => match multiline(&lav.value) {
+                Some(text) => details(&mut patches,&lav.loc.path,text,"")?,
+                None => rows.push((path_cell(&lav.loc.path),value_cell(&lav.value),String::new()))
+            },
+            Diff::Differ(lav1,lav2) if multiline(&lav1.value).is_some() || multiline(&lav2.value).is_some() => {
+                details(&mut patches,&lav2.loc.path,&lines(&lav1.value),&lines(&lav2.value))?
+            }
+            Diff::Differ(lav1,lav2) => rows.push((path_cell(&lav2.loc.path),value_cell(&lav1.value),value_cell(&lav2.value))),
+            Diff::Move(lav1,lav2) => {
+                let moved = if lav1.loc.path == lav2.loc.path { "_moved_".to_string() } else { format!("_moved from_ {}",path_cell(&lav1.loc.path)) };
+                rows.push((path_cell(&lav2.loc.path),moved,value_cell(&lav2.value)))
+            }
+            Diff::DocumentRenamed(loc1,loc2) => writeln!(out,"Renamed from {} to {}.\n",code(&loc1.doc.to_string()),code(&loc2.doc.to_string()))?
+        }
+    }
+    if !rows.is_empty() {
+        writeln!(out,"| Path | Old | New |\n| --- | --- | --- |")?;
+        for (path,old,new) in rows {
+            writeln!(out,"| {} | {} | {} |",path,old,new)?;
+        }
+        writeln!(out)?;
+    }
+    out.push_str(&patches);
+    Ok(())
+}
+
+/**
+ * A Markdown report of the differences, such as for a pull request comment. It starts
+ * with the totals, followed by a section for each document with differences.
+ */
+pub fn markdown_report(comparison: &Comparison, opts: &DiffOptions, diffs: &Diffs) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out,"**{}** between {} and {}\n",DiffStats::from(diffs),code(&opts.file1),code(&opts.file2))?;
+    for doc in comparison.document_diffs(diffs).iter().filter(|doc| !doc.diffs.is_empty()) {
+        document(&mut out,doc)?;
+    }
+    Ok(out.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_code() {
+        assert_eq!("`a.b`",code("a.b"));
+        assert_eq!("``a`b``",code("a`b"));
+        assert_eq!("`` `a ``",code("`a"));
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let y1 = YamlLoader::load_from_str("a: 1\nb: x|y\ns: \"l1\\nl2\\nl3\\n\"\nr: ''\n---\nsame: 1\n---\ngone: 1\n").unwrap();
+        let y2 = YamlLoader::load_from_str("a: 2\nb: x|z\nc: [z]\ns: \"l1\\nL2\\nl3\\n\"\n---\nsame: 1\n").unwrap();
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = Comparison::new(&opts,&None,y1,y2).unwrap();
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        assert_eq!(concat!(
+            "**6 differences (additions: 1, removals: 2, changes: 3)** between `f1.yaml` and `f2.yaml`\n\n",
+            "### `[0]`\n\n",
+            "| Path | Old | New |\n",
+            "| --- | --- | --- |\n",
+            "| `a` | `1` | `2` |\n",
+            "| `b` | `x\\|y` | `x\\|z` |\n",
+            "| `r` | `\"\"` |  |\n",
+            "| `c[0]` |  | `z` |\n\n",
+            "<details>\n<summary><code>s</code> (2 lines changed)</summary>\n\n",
+            "```diff\n--- original\n+++ modified\n@@ -1,3 +1,3 @@\n l1\n-l2\n+L2\n l3\n```\n</details>\n\n",
+            "### `[2]` (removed)\n\n",
+            "| Path | Old | New |\n",
+            "| --- | --- | --- |\n",
+            "| `gone` | `1` |  |"),markdown_report(&comparison,&opts,&diffs).unwrap());
+    }
+
+    #[test]
+    fn test_multiline_changed_to_scalar() {
+        let y1 = YamlLoader::load_from_str("a: \"x\\ny\"\nb: '1'\n").unwrap();
+        let y2 = YamlLoader::load_from_str("a: 5\nb: 1\n").unwrap();
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = Comparison::new(&opts,&None,y1,y2).unwrap();
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        assert_eq!(concat!(
+            "**2 differences (changes: 2)** between `f1.yaml` and `f2.yaml`\n\n",
+            "### `[0]`\n\n",
+            "| Path | Old | New |\n",
+            "| --- | --- | --- |\n",
+            "| `b` | `\"1\"` | `1` |\n\n",
+            "<details>\n<summary><code>a</code> (3 lines changed)</summary>\n\n",
+            "```diff\n--- original\n+++ modified\n@@ -1,2 +1 @@\n-x\n-y\n\\ No newline at end of file\n+5\n\\ No newline at end of file\n```\n</details>"),
+            markdown_report(&comparison,&opts,&diffs).unwrap());
+    }
+}
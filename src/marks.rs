@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fmt::{self,Display,Formatter};
+use yaml_rust::Event;
+use yaml_rust::parser::{MarkedEventReceiver,Parser};
+use yaml_rust::scanner::Marker;
+use crate::error::Result;
+use crate::keypath::{ItemKey,KeyPath};
+
+/** A position in a source file, with lines and columns numbered from 1 */
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Mark {
+    pub line: usize,
+    pub col: usize
+}
+
+impl Display for Mark {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f,"{}:{}",self.line,self.col)
+    }
+}
+
+/**
+ * The position of each value of a document, by path. The position of a hash value is
+ * that of its key.
+ */
+pub type Marks = HashMap<KeyPath,Mark>;
+
+/** A collection being parsed, with its path, or None if it's part of a hash key */
+enum Frame {
+    Array{path: Option<KeyPath>, index: usize},
+    Hash{path: Option<KeyPath>, key: Option<String>, in_value: bool}
+}
+
+#[derive(Default)]
+struct MarkRecorder {
+    docs: Vec<Marks>,
+    stack: Vec<Frame>
+}
+
+impl MarkRecorder {
+    fn record(&mut self, path: KeyPath, marker: Marker) {
+        if let Some(marks) = self.docs.last_mut() {
+            marks.insert(path,Mark{line: marker.line(), col: marker.col()+1});
+        }
+    }
+
+    /** Start a node, recording its position, and return its path unless it's a hash key */
+    fn begin(&mut self, marker: Marker, scalar: Option<&str>) -> Option<KeyPath> {
+        let (path,record) = match self.stack.last_mut() {
+            None => (Some(KeyPath::new()),true),
+            Some(Frame::Array{path,index}) => (path.as_ref().map(|p| p.push(ItemKey::Index(*index))),true),
+            Some(Frame::Hash{path,key,in_value: false}) => {
+                *key = scalar.map(String::from);
+                let key_path = path.as_ref().zip(key.as_ref()).map(|(p,k)| p.push(ItemKey::Key(k.clone())));
+                if let Some(key_path) = key_path {
+                    self.record(key_path,marker);
+                }
+                return None;
+            }
+            Some(Frame::Hash{path,key,in_value: true}) => {
+                (path.as_ref().zip(key.as_ref()).map(|(p,k)| p.push(ItemKey::Key(k.clone()))),false)
+            }
+        };
+        if let (Some(path),true) = (&path,record) {
+            self.record(path.clone(),marker);
+        }
+        path
+    }
+
+    /** Finish a node, moving on to the next element, key or value of its collection */
+    fn end(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Array{index,..}) => *index += 1,
+            Some(Frame::Hash{in_value,..}) => *in_value = !*in_value,
+            None => ()
+        }
+    }
+}
+
+impl MarkedEventReceiver for MarkRecorder {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::DocumentStart => {
+                self.docs.push(Marks::new());
+                self.stack.clear();
+            }
+            Event::Scalar(value,_,_,_) => {
+                self.begin(marker,Some(&value));
+                self.end();
+            }
+            Event::Alias(_) => {
+                self.begin(marker,None);
+                self.end();
+            }
+            Event::SequenceStart(_) => {
+                let path = self.begin(marker,None);
+                self.stack.push(Frame::Array{path, index: 0});
+            }
+            Event::MappingStart(_) => {
+                let path = self.begin(marker,None);
+                self.stack.push(Frame::Hash{path, key: None, in_value: false});
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.stack.pop();
+                self.end();
+            }
+            _ => ()
+        }
+    }
+}
+
+/** The positions of the values of each document in a YAML stream */
+pub fn mark_documents(text: &str) -> Result<Vec<Marks>> {
+    let mut recorder = MarkRecorder::default();
+    Parser::new(text.chars()).load(&mut recorder,true)?;
+    Ok(recorder.docs)
+}
+
+/** The position of the value at a path, or else of its closest ancestor that has one */
+pub fn find_mark(marks: &Marks, path: &KeyPath) -> Option<Mark> {
+    (0..=path.0.len()).rev().find_map(|len| marks.get(&path.truncated(len)).copied())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mark_documents() {
+        let text = "a: 1\nb:\n  c: [x, {d: 2}]\n  e:\n    - f\n    - g: 3\n---\n- h\n";
+        let marks = mark_documents(text).unwrap();
+        assert_eq!(2,marks.len());
+        let mark = |doc: usize, path: &str| marks[doc].get(&KeyPath::parse(path)).copied();
+        assert_eq!(Some(Mark{line: 1, col: 1}),mark(0,"a"));
+        assert_eq!(Some(Mark{line: 2, col: 1}),mark(0,"b"));
+        assert_eq!(Some(Mark{line: 3, col: 3}),mark(0,"b.c"));
+        assert_eq!(Some(Mark{line: 3, col: 10}),mark(0,"b.c[1]"));
+        assert_eq!(Some(Mark{line: 3, col: 11}),mark(0,"b.c[1].d"));
+        assert_eq!(Some(Mark{line: 5, col: 7}),mark(0,"b.e[0]"));
+        assert_eq!(Some(Mark{line: 6, col: 7}),mark(0,"b.e[1].g"));
+        assert_eq!(Some(Mark{line: 8, col: 3}),mark(1,"[0]"));
+        assert_eq!(Some(Mark{line: 6, col: 7}),find_mark(&marks[0],&KeyPath::parse("b.e[1].g.missing")));
+    }
+}
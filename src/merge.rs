@@ -0,0 +1,225 @@
+use std::fmt::{self,Display,Formatter};
+use clap::Args;
+use yaml_rust::Yaml;
+use crate::error::{Result,ResultExt};
+use crate::json::json_text;
+use crate::keypath::{ItemKey,KeyPath,KeyPathFuncs};
+use crate::patch::dump_documents;
+use crate::yamldiff::{document_key,load_file,Comparison,Diff,Diffs,DiffOptions,DocKey,Documents};
+
+/* Command line options of the merge subcommand */
+#[derive(Args)]
+pub struct MergeOpts {
+    #[clap(help="Common ancestor of the files to merge")]
+    base: String,
+    #[clap(help="YAML file to merge into")]
+    ours: String,
+    #[clap(help="YAML file to merge from")]
+    theirs: String,
+    #[clap(short,long,help="Match documents by Kubernetes metadata")]
+    k8s: bool,
+    #[clap(short('l'),long,help="Align arrays by longest common subsequence, rather than by position")]
+    lcs: bool
+}
+
+/**
+ * A value replaced by one side of a merge, at its path in the base document with any
+ * element matches resolved. The value is absent if it was removed.
+ */
+#[derive(Clone,Debug,PartialEq)]
+pub struct Change {
+    pub doc: DocKey,
+    pub path: KeyPath,
+    pub value: Option<Yaml>
+}
+
+impl Change {
+    fn describe(&self, side: &str) -> String {
+        match &self.value {
+            Some(value) => format!("{} changed to {}",side,json_text(value)),
+            None => format!("{} removed",side)
+        }
+    }
+}
+
+/** Changes made by both sides to the same value, or where one side's change contains the other's */
+#[derive(Debug,PartialEq)]
+pub struct Conflict {
+    pub ours: Change,
+    pub theirs: Change
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (ours,theirs) = (self.ours.describe("ours"),self.theirs.describe("theirs"));
+        if self.ours.path == self.theirs.path {
+            write!(f,"{} {}: {}, {}",self.ours.doc,self.ours.path,ours,theirs)
+        } else {
+            write!(f,"{} {}: {}; {}: {}",self.ours.doc,self.ours.path,ours,self.theirs.path,theirs)
+        }
+    }
+}
+
+/** The result of a merge: the merged documents, and the changes that couldn't be merged */
+pub struct Merged {
+    pub docs: Vec<Yaml>,
+    pub conflicts: Vec<Conflict>
+}
+
+/** Parsed documents indexed by key, without the changes made to documents when comparing them */
+fn keyed(docs: Vec<Yaml>, opts: &DiffOptions) -> Result<Documents> {
+    let mut result = Documents::new();
+    for (position,yaml) in docs.into_iter().enumerate() {
+        if opts.k8s && yaml.is_null() { continue; }
+        result.insert(document_key(&yaml,position,opts,&None)?,yaml);
+    }
+    Ok(result)
+}
+
+fn contains(outer: &KeyPath, inner: &KeyPath) -> bool {
+    outer.0.len() <= inner.0.len() && outer.0[..] == inner.0[..outer.0.len()]
+}
+
+/**
+ * The changes one side made to the base documents, from the differences between them.
+ * Elements can't be added to, removed from or moved within an array independently of
+ * changes made to the array by the other side, so such an array is changed as a whole.
+ */
+fn changes(base: &Documents, side: &Documents, diffs: &Diffs) -> Result<Vec<Change>> {
+    let mut found: Vec<(&DocKey,KeyPath,KeyPath,bool)> = Vec::new();
+    for diff in diffs {
+        let (doc,path,side_path,removed,in_array) = match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => (&*lav.loc.doc,&lav.loc.path,&lav.loc.path,false,true),
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => (&*lav.loc.doc,&lav.loc.path,&lav.loc.path,true,true),
+            Diff::Differ(lav1,lav2) => (&*lav1.loc.doc,&lav1.loc.path,&lav2.loc.path,false,false),
+            Diff::Move(lav1,lav2) => (&*lav1.loc.doc,&lav1.loc.path,&lav2.loc.path,false,true),
+            Diff::DocumentRenamed(_,_) => continue
+        };
+        let restructured = in_array && !matches!(path.0.last(),None|Some(ItemKey::Key(_)));
+        let (path,side_path,removed) = if restructured {
+            (path.truncated(path.0.len()-1),side_path.truncated(side_path.0.len()-1),false)
+        } else {
+            (path.clone(),side_path.clone(),removed)
+        };
+        let path = path.resolved(base.get(doc).unwrap_or(&Yaml::Null))?;
+        found.push((doc,path,side_path,removed));
+    }
+    let mut result: Vec<Change> = Vec::new();
+    for (n,(doc,path,side_path,removed)) in found.iter().enumerate() {
+        let subsumed = found.iter().enumerate().any(|(m,(other_doc,other_path,_,_))| {
+            other_doc == doc && contains(other_path,path) && (other_path.0.len() < path.0.len() || m < n)
+        });
+        if !subsumed {
+            let value = if *removed { None } else { Some(side[*doc].get_at_path(side_path.clone())?.clone()) };
+            result.push(Change{doc: (*doc).clone(), path: path.clone(), value});
+        }
+    }
+    Ok(result)
+}
+
+fn apply(docs: &mut Documents, change: Change) -> Result<()> {
+    match (docs.get_mut(&change.doc),change.value) {
+        (Some(doc),Some(value)) if change.path.0.is_empty() => *doc = value,
+        (None,Some(value)) => { docs.insert(change.doc,value); }
+        (_,None) if change.path.0.is_empty() => { docs.remove(&change.doc); }
+        (Some(doc),Some(value)) => doc.set_at_path(change.path,value)?,
+        (Some(doc),None) => { doc.remove_at_path(change.path)?; }
+        (None,None) => ()
+    }
+    Ok(())
+}
+
+/**
+ * Merge the changes made to the base documents by their side into our documents. The
+ * options give the names of the base and our files, and how documents are compared. Changes
+ * made by only one side, or identically by both, are merged. Where both sides changed the
+ * same value differently, or one side changed a value within one that the other replaced
+ * or removed, our value is kept and the conflict reported.
+ */
+pub fn merge(opts: &DiffOptions, base: Vec<Yaml>, ours: Vec<Yaml>, theirs: Vec<Yaml>) -> Result<Merged> {
+    let opts = &DiffOptions{subtrees: true, ..opts.clone()};
+    let base_docs = keyed(base.clone(),opts)?;
+    let (ours_docs,theirs_docs) = (keyed(ours.clone(),opts)?,keyed(theirs.clone(),opts)?);
+    let ours_diffs = Comparison::new(opts,&None,base.clone(),ours)?.diffs(opts,&None)?;
+    let theirs_diffs = Comparison::new(opts,&None,base,theirs)?.diffs(opts,&None)?;
+    let ours_changes = changes(&base_docs,&ours_docs,&ours_diffs)?;
+    let theirs_changes = changes(&base_docs,&theirs_docs,&theirs_diffs)?;
+    let mut merged = ours_docs;
+    let mut conflicts = Vec::new();
+    for change in theirs_changes {
+        let overlapping = ours_changes.iter().find(|ours| {
+            ours.doc == change.doc && (contains(&ours.path,&change.path) || contains(&change.path,&ours.path))
+        });
+        match overlapping {
+            None => apply(&mut merged,change)?,
+            Some(ours) if *ours == change => (),
+            Some(ours) => conflicts.push(Conflict{ours: ours.clone(), theirs: change})
+        }
+    }
+    Ok(Merged{docs: merged.into_iter().map(|(_,doc)| doc).collect(),conflicts})
+}
+
+pub fn do_merge(opts: &MergeOpts) -> Result<i32> {
+    let base = load_file(&opts.base).chain_err(|| format!("while parsing {}",&opts.base))?;
+    let ours = load_file(&opts.ours).chain_err(|| format!("while parsing {}",&opts.ours))?;
+    let theirs = load_file(&opts.theirs).chain_err(|| format!("while parsing {}",&opts.theirs))?;
+    let diff_opts = DiffOptions{k8s: opts.k8s, lcs: opts.lcs, ..DiffOptions::new(&opts.base,&opts.ours)};
+    let merged = merge(&diff_opts,base,ours,theirs)?;
+    println!("{}",dump_documents(&merged.docs)?);
+    for conflict in &merged.conflicts {
+        eprintln!("conflict: {}",conflict);
+    }
+    Ok(if merged.conflicts.is_empty() {0} else {1})
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn load(text: &str) -> Vec<Yaml> {
+        YamlLoader::load_from_str(text).unwrap()
+    }
+
+    fn merged(opts: &DiffOptions, base: &str, ours: &str, theirs: &str) -> Merged {
+        merge(opts,load(base),load(ours),load(theirs)).unwrap()
+    }
+
+    #[test]
+    fn test_merge() {
+        let opts = DiffOptions::new("base.yaml","ours.yaml");
+        let result = merged(&opts,
+            "a: 1\nb: {c: 2, d: 3}\nlist: [x, y]\ngone: 1\n---\nsecond: 1\n",
+            "a: 2\nb: {c: 2, d: 4}\nlist: [x, y]\ngone: 1\nours: 1\n---\nsecond: 1\n",
+            "a: 2\nb: {c: 5, d: 3}\nlist: [w, x, y]\n---\nsecond: 2\n---\nthird: 3\n");
+        assert!(result.conflicts.is_empty());
+        assert_eq!(load("a: 2\nb: {c: 5, d: 4}\nlist: [w, x, y]\nours: 1\n---\nsecond: 2\n---\nthird: 3\n"),result.docs);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let opts = DiffOptions::new("base.yaml","ours.yaml");
+        let result = merged(&opts,
+            "a: 1\nb: {c: 2}\nlist: [x, y]\nkept: 1\n",
+            "a: 2\nlist: [x, y, z]\nkept: 1\n",
+            "a: 3\nb: {c: 3}\nlist: [x, w]\nkept: 2\n");
+        assert_eq!(load("a: 2\nlist: [x, y, z]\nkept: 2\n"),result.docs);
+        let conflicts: Vec<String> = result.conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(vec![
+            "[0] a: ours changed to 2, theirs changed to 3".to_string(),
+            "[0] b: ours removed; b.c: theirs changed to 3".to_string(),
+            "[0] list: ours changed to [\"x\",\"y\",\"z\"]; list[1]: theirs changed to \"w\"".to_string()
+        ],conflicts);
+    }
+
+    #[test]
+    fn test_k8s_merge() {
+        let opts = DiffOptions{k8s: true, ..DiffOptions::new("base.yaml","ours.yaml")};
+        let pod = |a: &str, b: &str| format!(
+            "apiVersion: v1\nkind: Pod\nmetadata: {{name: p}}\nspec:\n  containers: [{{name: a, image: {}}}, {{name: b, image: {}}}]\n",a,b);
+        let cm = "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: cm}\n";
+        let result = merged(&opts,&format!("{}---\n{}",cm,pod("a1","b1")),&pod("a2","b1"),&format!("{}---\n{}",cm,pod("a1","b2")));
+        assert!(result.conflicts.is_empty());
+        assert_eq!(load(&pod("a2","b2")),result.docs);
+    }
+}
@@ -0,0 +1,157 @@
+use yaml_rust::Yaml;
+use yaml_rust::yaml;
+use crate::error::{ErrorKind,Result};
+use crate::keypath::{scalar_string,ItemKey,KeyPath,KeyPathFuncs};
+use crate::yamldiff::{Comparison,Diff,Diffs,DocKey};
+
+/**
+ * The path at which a difference is recorded in a merge patch. Arrays can't be merged
+ * element by element, so a difference within an array is recorded as a replacement of
+ * the whole of the outermost array containing it.
+ */
+fn merge_path(path: &KeyPath) -> (KeyPath,bool) {
+    match path.0.iter().position(|item| !matches!(item,ItemKey::Key(_))) {
+        Some(depth) => (path.truncated(depth),true),
+        None => (path.clone(),false)
+    }
+}
+
+/**
+ * The path of a null in a value to be merged at a path, other than within an array. Merging
+ * a null removes the value instead, so it can't be recorded in a merge patch. 
+ */
+fn null_member(value: &Yaml, path: KeyPath) -> Option<KeyPath> {
+    match value {
+        Yaml::Null if !path.0.is_empty() => Some(path),
+        Yaml::Hash(hash) => hash.iter().find_map(|(k,v)| null_member(v,path.push(ItemKey::Key(scalar_string(k).unwrap_or_default())))),
+        _ => None
+    }
+}
+
+/** Set a value in a merge patch, where an empty path replaces the whole patch */
+fn set_value(patch: &mut Yaml, path: &KeyPath, value: Yaml) -> Result<()> {
+    if path.0.is_empty() {
+        *patch = value;
+        Ok(())
+    } else {
+        patch.set_at_path(path.clone(),value)
+    }
+}
+
+/**
+ * Include the values identifying the modified document, so that the patch can be
+ * matched up with it again. These are unchanged, so they make no difference when merged.
+ */
+fn identify(patch: &mut Yaml, doc: &Yaml, key: &DocKey) -> Result<()> {
+    let paths: Vec<KeyPath> = match key {
+        DocKey::Position(_) => Vec::new(),
+        DocKey::K8S(_) => ["apiVersion","kind","metadata.name","metadata.namespace"].iter().map(|p| KeyPath::parse(p)).collect(),
+        DocKey::Properties(props) => props.iter().map(|(p,_)| KeyPath::parse(p)).collect()
+    };
+    for path in paths {
+        if let (Ok(value),(path,false)) = (doc.get_at_path(path.clone()),merge_path(&path)) {
+            if !value.is_null() {
+                set_value(patch,&path,value.clone())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * The sparse document that, merged onto the original document as described by RFC 7386,
+ * gives the modified document. Removed values are recorded as null, so it is an error
+ * for a value outside of an array to be set to null.
+ */
+fn merge_patch(doc2: &Yaml, key1: &DocKey, key2: &DocKey, diffs: &Diffs) -> Result<Option<Yaml>> {
+    let mut patch = Yaml::Hash(yaml::Hash::new());
+    let mut changed = false;
+    for diff in diffs.iter().filter(|diff| diff.is_between(key1,key2)) {
+        let (path,value) = match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => (&lav.loc.path,Some(&*lav.value)),
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => (&lav.loc.path,None),
+            Diff::Differ(_,lav2) | Diff::Move(_,lav2) => (&lav2.loc.path,Some(&*lav2.value)),
+            Diff::DocumentRenamed(_,_) => continue
+        };
+        let (path,in_array) = merge_path(path);
+        let value = match value {
+            _ if in_array => doc2.get_at_path(path.clone())?.clone(),
+            Some(value) => match null_member(value,path.clone()) {
+                Some(null) => return Err(ErrorKind::NullInMergePatch(format!("{} {}",key2,null)).into()),
+                None => value.clone()
+            },
+            None => Yaml::Null
+        };
+        set_value(&mut patch,&path,value)?;
+        changed = true;
+    }
+    if changed && patch.as_hash().is_some() {
+        identify(&mut patch,doc2,key2)?;
+    }
+    Ok(if changed { Some(patch) } else { None })
+}
+
+/**
+ * An RFC 7386 merge patch for each pair of documents compared that have differences,
+ * in the order of the original file. As with JSON Patch, the differences should have
+ * been found with the `subtrees` option.
+ */
+pub fn merge_patches(comparison: &Comparison, diffs: &Diffs) -> Result<Vec<Yaml>> {
+    let mut result = Vec::new();
+    for (key1,key2) in comparison.document_pairs() {
+        if let Some(patch) = merge_patch(&comparison.modified[key2],key1,key2,diffs)? {
+            result.push(patch);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+    use crate::yamldiff::DiffOptions;
+
+    fn patches(opts: &DiffOptions, original: &str, modified: &str) -> Vec<Yaml> {
+        let y1 = YamlLoader::load_from_str(original).unwrap();
+        let y2 = YamlLoader::load_from_str(modified).unwrap();
+        let comparison = Comparison::new(opts,&None,y1,y2).unwrap();
+        let diffs = comparison.diffs(opts,&None).unwrap();
+        merge_patches(&comparison,&diffs).unwrap()
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let opts = DiffOptions{subtrees: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let result = patches(&opts,
+            "a: 1\nb: {c: 2, d: 3}\nlist: [{x: 1}, {x: 2}]\nsame: 1\n---\nz: 1\n",
+            "a: 2\nb: {c: 2}\ne: {f: [g]}\nlist: [{x: 1}, {x: 3}]\nsame: 1\n---\nz: 1\n");
+        let expected = YamlLoader::load_from_str("a: 2\nb: {d: ~}\nlist: [{x: 1}, {x: 3}]\ne: {f: [g]}\n").unwrap();
+        assert_eq!(expected,result);
+    }
+
+    #[test]
+    fn test_k8s_merge_patch() {
+        let opts = DiffOptions{subtrees: true, k8s: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let result = patches(&opts,
+            "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: cm, namespace: ns}\ndata: {a: '1', b: '2'}\n",
+            "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: cm, namespace: ns}\ndata: {a: '1', b: '3'}\n");
+        let expected = YamlLoader::load_from_str(
+            "data: {b: '3'}\napiVersion: v1\nkind: ConfigMap\nmetadata: {name: cm, namespace: ns}\n").unwrap();
+        assert_eq!(expected,result);
+    }
+
+    #[test]
+    fn test_merge_patch_nulls() {
+        let opts = DiffOptions{subtrees: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        let result = patches(&opts,"a: 1\nlist: [1, 2]\n","a: 1\nlist: [1, ~]\n");
+        assert_eq!(YamlLoader::load_from_str("list: [1, ~]\n").unwrap(),result);
+        for modified in ["a: ~\n","a: 1\nb: ~\n","a: 1\nb: {c: {d: ~}}\n"] {
+            let y1 = YamlLoader::load_from_str("a: 1\n").unwrap();
+            let y2 = YamlLoader::load_from_str(modified).unwrap();
+            let comparison = Comparison::new(&opts,&None,y1,y2).unwrap();
+            let diffs = comparison.diffs(&opts,&None).unwrap();
+            assert!(merge_patches(&comparison,&diffs).is_err(),"{}",modified);
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use clap::Args;
+use yaml_rust::{Yaml,YamlLoader,YamlEmitter};
+use crate::error::{ErrorKind,Result,ResultExt};
+use crate::json::json_text;
+use crate::keypath::{scalar_string,ItemKey,KeyPath,KeyPathFuncs};
+use crate::yamldiff::{document_key,load_file,DiffOptions,DocKey};
+
+/* Command line options of the patch subcommand */
+#[derive(Args)]
+pub struct PatchOpts {
+    #[clap(help="YAML file to patch")]
+    file: String,
+    #[clap(help="Patch file, either an RFC 6902 JSON Patch or a list of yamldiff patch records")]
+    patch: String,
+    #[clap(short,long,help="Identify documents by Kubernetes metadata")]
+    k8s: bool,
+    #[clap(short,long,value_name("KEY"),help="Document to patch, by position or key, unless given by a patch record [default: the first document]")]
+    document: Option<String>
+}
+
+/** An RFC 6902 operation. Paths are JSON Pointers. */
+#[derive(Debug,PartialEq)]
+pub enum JsonOp {
+    Add{path: String, value: Yaml},
+    Remove{path: String},
+    Replace{path: String, value: Yaml},
+    Move{from: String, path: String},
+    Copy{from: String, path: String},
+    Test{path: String, value: Yaml}
+}
+
+/**
+ * A change to a single value. The old value is absent if the value is being added,
+ * and the new value is absent if it is being removed.
+ */
+#[derive(Debug,PartialEq)]
+pub struct Record {
+    pub document: Option<String>,
+    pub path: KeyPath,
+    pub old: Option<Yaml>,
+    pub new: Option<Yaml>
+}
+
+/** The changes to be applied to a YAML file */
+#[derive(Debug,PartialEq)]
+pub enum Patch {
+    Json(Vec<JsonOp>),
+    Records(Vec<Record>)
+}
+
+fn invalid(reason: String) -> crate::error::Error {
+    ErrorKind::InvalidPatch(reason).into()
+}
+
+/** An optional field of a patch entry, distinguishing a null value from a missing one */
+fn optional_field(entry: &Yaml, field: &str) -> Option<Yaml> {
+    match &entry[field] {
+        Yaml::BadValue => None,
+        value => Some(value.clone())
+    }
+}
+
+fn string_field(entry: &Yaml, field: &str, n: usize) -> Result<String> {
+    match &entry[field] {
+        Yaml::String(s) => Ok(s.clone()),
+        Yaml::Integer(i) => Ok(i.to_string()),
+        _ => Err(invalid(format!("entry {} has no '{}'",n+1,field)))
+    }
+}
+
+fn value_field(entry: &Yaml, field: &str, n: usize) -> Result<Yaml> {
+    optional_field(entry,field).ok_or_else(|| invalid(format!("entry {} has no '{}'",n+1,field)))
+}
+
+impl JsonOp {
+    fn parse(entry: &Yaml, n: usize) -> Result<JsonOp> {
+        let path = string_field(entry,"path",n)?;
+        match string_field(entry,"op",n)?.as_str() {
+            "add" => Ok(JsonOp::Add{path, value: value_field(entry,"value",n)?}),
+            "remove" => Ok(JsonOp::Remove{path}),
+            "replace" => Ok(JsonOp::Replace{path, value: value_field(entry,"value",n)?}),
+            "move" => Ok(JsonOp::Move{from: string_field(entry,"from",n)?, path}),
+            "copy" => Ok(JsonOp::Copy{from: string_field(entry,"from",n)?, path}),
+            "test" => Ok(JsonOp::Test{path, value: value_field(entry,"value",n)?}),
+            op => Err(invalid(format!("entry {} has unknown op '{}'",n+1,op)))
+        }
+    }
+
+    fn path(&self) -> &str {
+        match self {
+            JsonOp::Add{path,..} | JsonOp::Remove{path} | JsonOp::Replace{path,..} |
+            JsonOp::Move{path,..} | JsonOp::Copy{path,..} | JsonOp::Test{path,..} => path
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            JsonOp::Add{..} => "add",
+            JsonOp::Remove{..} => "remove",
+            JsonOp::Replace{..} => "replace",
+            JsonOp::Move{..} => "move",
+            JsonOp::Copy{..} => "copy",
+            JsonOp::Test{..} => "test"
+        }
+    }
+
+    fn apply(&self, doc: &mut Yaml) -> Result<()> {
+        match self {
+            JsonOp::Add{path,value} => insert(doc,path,value.clone()),
+            JsonOp::Remove{path} => remove(doc,path).map(|_| ()),
+            JsonOp::Replace{path,value} => {
+                let path = pointer_path(doc,path,false)?;
+                if path.0.is_empty() {
+                    *doc = value.clone();
+                    Ok(())
+                } else {
+                    doc.get_at_path(path.clone())?;
+                    doc.set_at_path(path,value.clone())
+                }
+            }
+            JsonOp::Move{from,path} => {
+                let value = remove(doc,from)?;
+                insert(doc,path,value)
+            }
+            JsonOp::Copy{from,path} => {
+                let value = doc.get_at_path(pointer_path(doc,from,false)?)?.clone();
+                insert(doc,path,value)
+            }
+            JsonOp::Test{path,value} => {
+                let found = doc.get_at_path(pointer_path(doc,path,false)?)?;
+                if found == value {
+                    Ok(())
+                } else {
+                    Err(ErrorKind::PatchConflicts(vec![format!("expected {}, found {}",json_text(value),json_text(found))]).into())
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Convert a JSON Pointer to a path in the document, treating each token as an array
+ * index or hash key according to the value it is applied to. The `-` token for the end
+ * of an array is only allowed when adding.
+ */
+fn pointer_path(doc: &Yaml, pointer: &str, adding: bool) -> Result<KeyPath> {
+    let mut path = KeyPath::new();
+    if pointer.is_empty() {
+        return Ok(path);
+    }
+    let tokens = pointer.strip_prefix('/').ok_or_else(|| invalid(format!("'{}' is not a JSON pointer",pointer)))?;
+    let mut current = doc;
+    for token in tokens.split('/') {
+        let token = token.replace("~1","/").replace("~0","~");
+        let item = match current {
+            Yaml::Array(a) if token == "-" && adding => ItemKey::Index(a.len()),
+            Yaml::Array(_) => match token.parse::<usize>() {
+                Ok(index) => ItemKey::Index(index),
+                Err(_) => return Err(ErrorKind::KeyNotFound(path.push(ItemKey::Key(token)).to_string()).into())
+            },
+            _ => ItemKey::Key(token)
+        };
+        current = match &item {
+            ItemKey::Index(index) => &current[*index],
+            ItemKey::Key(key) => &current[key.as_str()],
+            ItemKey::Match(_,_) => unreachable!()
+        };
+        path.0.push(item);
+    }
+    Ok(path)
+}
+
+fn insert(doc: &mut Yaml, pointer: &str, value: Yaml) -> Result<()> {
+    let path = pointer_path(doc,pointer,true)?;
+    if path.0.is_empty() {
+        *doc = value;
+        Ok(())
+    } else {
+        doc.insert_at_path(path,value)
+    }
+}
+
+fn remove(doc: &mut Yaml, pointer: &str) -> Result<Yaml> {
+    let path = pointer_path(doc,pointer,false)?;
+    if path.0.is_empty() {
+        Ok(std::mem::replace(doc,Yaml::Null))
+    } else {
+        doc.remove_at_path(path)
+    }
+}
+
+impl Record {
+    fn parse(entry: &Yaml, n: usize) -> Result<Record> {
+        let document = match &entry["document"] {
+            Yaml::BadValue => None,
+            _ => Some(string_field(entry,"document",n)?)
+        };
+        let path = KeyPath::parse(&string_field(entry,"path",n)?);
+        let old = optional_field(entry,"old");
+        let new = optional_field(entry,"new");
+        if old.is_none() && new.is_none() {
+            return Err(invalid(format!("entry {} has neither 'old' nor 'new'",n+1)));
+        }
+        Ok(Record{document,path,old,new})
+    }
+
+    fn apply(&self, doc: &mut Yaml) -> Result<()> {
+        let current = doc.get_at_path(self.path.clone()).ok();
+        if current != self.old.as_ref() {
+            let describe = |value: Option<&Yaml>| value.map(json_text).unwrap_or_else(|| "nothing".to_string());
+            let message = format!("{}: expected {}, found {}",self.path,describe(self.old.as_ref()),describe(current));
+            return Err(ErrorKind::PatchConflicts(vec![message]).into());
+        }
+        match (&self.new,current.is_some(),self.path.0.last()) {
+            (None,_,_) => doc.remove_at_path(self.path.clone()).map(|_| ()),
+            (Some(value),false,Some(ItemKey::Index(_))) => doc.insert_at_path(self.path.clone(),value.clone()),
+            (Some(value),false,Some(ItemKey::Match(key,matched))) => append_matched(doc,&self.path,key,matched,value),
+            (Some(value),_,_) if self.path.0.is_empty() => { *doc = value.clone(); Ok(()) },
+            (Some(value),_,_) => doc.set_at_path(self.path.clone(),value.clone())
+        }
+    }
+}
+
+/**
+ * Add an element matched by the value of one of its keys to the end of its array, which
+ * it must have the value of that key to be matched by.
+ */
+fn append_matched(doc: &mut Yaml, path: &KeyPath, key: &str, matched: &str, value: &Yaml) -> Result<()> {
+    if scalar_string(&value[key]).as_deref() != Some(matched) {
+        return Err(invalid(format!("{}: new value doesn't have '{}' of '{}'",path,key,matched)));
+    }
+    let array = path.truncated(path.0.len()-1);
+    let len = match doc.get_at_path(array.clone())? {
+        Yaml::Array(elements) => elements.len(),
+        _ => return Err(ErrorKind::WrongType(array.to_string()).into())
+    };
+    doc.insert_at_path(array.push(ItemKey::Index(len)),value.clone())
+}
+
+/** The conflicts described by an error, or the error itself if it isn't a conflict */
+fn conflicts(error: crate::error::Error, context: &str) -> Vec<String> {
+    match error.kind() {
+        ErrorKind::PatchConflicts(conflicts) => conflicts.iter().map(|c| format!("{}{}",context,c)).collect(),
+        _ => vec![format!("{}{}",context,error)]
+    }
+}
+
+impl Patch {
+    /**
+     * Parse a patch, which is a list of either RFC 6902 operations or yamldiff patch
+     * records, in JSON or YAML.
+     */
+    pub fn parse(text: &str) -> Result<Patch> {
+        let docs = YamlLoader::load_from_str(text)?;
+        let entries = match docs.first() {
+            None => return Ok(Patch::Records(Vec::new())),
+            Some(Yaml::Array(entries)) if docs.len() == 1 => entries,
+            Some(_) => return Err(invalid("a patch must be a single list".to_string()))
+        };
+        if entries.iter().any(|entry| !entry["op"].is_badvalue()) {
+            let ops = entries.iter().enumerate().map(|(n,entry)| JsonOp::parse(entry,n));
+            Ok(Patch::Json(ops.collect::<Result<Vec<JsonOp>>>()?))
+        } else {
+            let records = entries.iter().enumerate().map(|(n,entry)| Record::parse(entry,n));
+            Ok(Patch::Records(records.collect::<Result<Vec<Record>>>()?))
+        }
+    }
+
+    /**
+     * Apply the patch to the documents of a file. A JSON Patch applies to the given
+     * document, and is abandoned at the first operation that can't be applied. Each record
+     * applies to the document it names, or else the given document, and only records that
+     * find the value they expect are applied. Any conflicts are reported in the error.
+     */
+    pub fn apply(&self, docs: &mut [Yaml], opts: &DiffOptions, document: Option<&str>) -> Result<()> {
+        let keys: Vec<Option<DocKey>> = docs.iter().enumerate()
+            .map(|(position,doc)| document_key(doc,position,opts,&None).ok()).collect();
+        let count = docs.len();
+        let find = |selector: Option<&str>| -> Result<usize> {
+            let selector = match selector {
+                None => return if count == 0 { Err(ErrorKind::DocumentNotFound("0".to_string()).into()) } else { Ok(0) },
+                Some(selector) => selector
+            };
+            let position = keys.iter().position(|key| match key {
+                Some(DocKey::Position(n)) => selector == n.to_string() || selector == format!("[{}]",n),
+                Some(key) => selector == key.to_string(),
+                None => false
+            });
+            position.ok_or_else(|| ErrorKind::DocumentNotFound(selector.to_string()).into())
+        };
+        let mut found = Vec::new();
+        match self {
+            Patch::Json(ops) => {
+                let doc = &mut docs[find(document)?];
+                for (n,op) in ops.iter().enumerate() {
+                    if let Err(e) = op.apply(doc) {
+                        found.extend(conflicts(e,&format!("operation {} ({} {}): ",n+1,op.name(),op.path())));
+                        break;
+                    }
+                }
+            }
+            Patch::Records(records) => {
+                for record in records {
+                    let result = find(record.document.as_deref().or(document)).and_then(|n| record.apply(&mut docs[n]));
+                    if let Err(e) = result {
+                        found.extend(conflicts(e,""));
+                    }
+                }
+            }
+        }
+        if found.is_empty() { Ok(()) } else { Err(ErrorKind::PatchConflicts(found).into()) }
+    }
+}
+
+/** Format documents as a YAML stream */
+pub(crate) fn dump_documents(docs: &[Yaml]) -> Result<String> {
+    let mut out = String::new();
+    for doc in docs {
+        if !out.is_empty() { out.push('\n'); }
+        YamlEmitter::new(&mut out).dump(doc)?;
+    }
+    Ok(out)
+}
+
+pub fn do_patch(opts: &PatchOpts) -> Result<i32> {
+    let mut docs = load_file(&opts.file).chain_err(|| format!("while parsing {}",&opts.file))?;
+    let text = std::fs::read_to_string(&opts.patch).chain_err(|| format!("while reading {}",&opts.patch))?;
+    let patch = Patch::parse(&text).chain_err(|| format!("while parsing {}",&opts.patch))?;
+    let diff_opts = DiffOptions{k8s: opts.k8s, ..DiffOptions::new(&opts.file,&opts.file)};
+    patch.apply(&mut docs,&diff_opts,opts.document.as_deref()).chain_err(|| format!("can't patch {}",&opts.file))?;
+    println!("{}",dump_documents(&docs)?);
+    Ok(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn load(text: &str) -> Vec<Yaml> {
+        YamlLoader::load_from_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_patch() {
+        let patch = Patch::parse(r#"[{"op":"add","path":"/a~1b","value":{"x":[1,"s",null]}},{"op":"move","from":"/c","path":"/d"}]"#).unwrap();
+        assert_eq!(Patch::Json(vec![
+            JsonOp::Add{path: "/a~1b".to_string(), value: load("{x: [1, s, ~]}").remove(0)},
+            JsonOp::Move{from: "/c".to_string(), path: "/d".to_string()}
+        ]),patch);
+        let patch = Patch::parse("- path: a.b\n  old: 1\n  new: 2\n- document: 1\n  path: c\n  old: ~\n").unwrap();
+        assert_eq!(Patch::Records(vec![
+            Record{document: None, path: KeyPath::parse("a.b"), old: Some(Yaml::Integer(1)), new: Some(Yaml::Integer(2))},
+            Record{document: Some("1".to_string()), path: KeyPath::parse("c"), old: Some(Yaml::Null), new: None}
+        ]),patch);
+        assert!(Patch::parse("[{op: frobnicate, path: /a}]").is_err());
+        assert!(Patch::parse("[{path: a}]").is_err());
+    }
+
+    #[test]
+    fn test_apply_json_patch() {
+        let mut docs = load("a: {b: 1}\nlist: [x, y]\n---\nsecond: true\n");
+        let patch = Patch::parse(r#"[
+            {"op": "test", "path": "/a/b", "value": 1},
+            {"op": "replace", "path": "/a/b", "value": 2},
+            {"op": "add", "path": "/list/-", "value": "z"},
+            {"op": "move", "from": "/list/0", "path": "/list/1"},
+            {"op": "copy", "from": "/a", "path": "/c"},
+            {"op": "remove", "path": "/a/b"}
+        ]"#).unwrap();
+        patch.apply(&mut docs,&DiffOptions::default(),None).unwrap();
+        assert_eq!(load("a: {}\nlist: [y, x, z]\nc: {b: 2}\n---\nsecond: true\n"),docs);
+        let patch = Patch::parse(r#"[{"op": "replace", "path": "/second", "value": false}]"#).unwrap();
+        patch.apply(&mut docs,&DiffOptions::default(),Some("1")).unwrap();
+        assert_eq!(Yaml::Boolean(false),docs[1]["second"]);
+    }
+
+    #[test]
+    fn test_json_patch_conflict() {
+        let mut docs = load("a: {b: 1}\n");
+        let patch = Patch::parse(r#"[{"op": "test", "path": "/a/b", "value": 2}, {"op": "remove", "path": "/a"}]"#).unwrap();
+        let e = patch.apply(&mut docs,&DiffOptions::default(),None).unwrap_err();
+        match e.kind() {
+            ErrorKind::PatchConflicts(conflicts) => {
+                assert_eq!(vec!["operation 1 (test /a/b): expected 2, found 1".to_string()],*conflicts)
+            }
+            kind => panic!("Unexpected error {:?}",kind)
+        }
+    }
+
+    #[test]
+    fn test_apply_records() {
+        let k8s = "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: cm}\ndata: {a: '1'}\n";
+        let mut docs = load(&format!("x: 1\nlist: [{{name: n, v: 1}}]\n---\n{}",k8s));
+        let patch = Patch::parse(concat!(
+            "- {path: x, old: 1, new: 2}\n",
+            "- {path: 'list[name=n].v', old: 1}\n",
+            "- {path: 'list[1]', new: added}\n",
+            "- {path: new.nested, new: value}\n",
+            "- {document: 'v1,ConfigMap,cm', path: data.a, old: '1', new: '2'}\n")).unwrap();
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
+        patch.apply(&mut docs,&opts,None).unwrap();
+        assert_eq!(load(&format!("x: 2\nlist: [{{name: n}}, added]\nnew: {{nested: value}}\n---\n{}",k8s.replace("'1'","'2'"))),docs);
+    }
+
+    #[test]
+    fn test_record_conflicts() {
+        let mut docs = load("x: 1\ny: 2\n");
+        let patch = Patch::parse("- {path: x, old: 3, new: 4}\n- {path: y, old: 2, new: 5}\n- {path: z, new: 6}\n- {path: y, new: 7}\n").unwrap();
+        let e = patch.apply(&mut docs,&DiffOptions::default(),None).unwrap_err();
+        match e.kind() {
+            ErrorKind::PatchConflicts(conflicts) => assert_eq!(vec![
+                "x: expected 3, found 1".to_string(),
+                "y: expected nothing, found 5".to_string()
+            ],*conflicts),
+            kind => panic!("Unexpected error {:?}",kind)
+        }
+        assert_eq!(Yaml::Integer(5),docs[0]["y"]);
+        assert_eq!(Yaml::Integer(6),docs[0]["z"]);
+    }
+
+    #[test]
+    fn test_append_matched_record() {
+        let mut docs = load("containers: [{name: web, image: 'web:1.0'}]\n");
+        let patch = Patch::parse("- path: 'containers[name=sidecar]'\n  new: {name: sidecar, image: 'proxy:0.9'}\n").unwrap();
+        patch.apply(&mut docs,&DiffOptions::default(),None).unwrap();
+        assert_eq!(load("containers: [{name: web, image: 'web:1.0'}, {name: sidecar, image: 'proxy:0.9'}]\n"),docs);
+        let patch = Patch::parse("- path: 'containers[name=other]'\n  new: {name: sidecar}\n").unwrap();
+        let e = patch.apply(&mut docs,&DiffOptions::default(),None).unwrap_err();
+        assert_eq!(vec!["invalid patch: containers[name=other]: new value doesn't have 'name' of 'other'".to_string()],conflicts(e,""));
+    }
+}
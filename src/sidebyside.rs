@@ -0,0 +1,219 @@
+use std::io;
+use ansi_colors::ColouredStr;
+use diffy::{create_patch,Line};
+use yaml_rust::Yaml;
+use crate::error::Result;
+use crate::json::scalar_text;
+use crate::yamldiff::{colorize_with,is_subtree,yaml_lines,Comparison,Diff,Diffs,DiffOptions,DocumentDiffs,LocationAndValue};
+
+/** Width of the output if it isn't given and standard output isn't a terminal */
+pub const DEFAULT_WIDTH: usize = 120;
+
+const SEPARATOR: &str = " │ ";
+
+/** How a cell is coloured */
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+enum Tone {
+    Plain,
+    Removed,
+    Added,
+    Moved,
+    Heading
+}
+
+/** Text shown in one column, which is wrapped if it's wider than the column */
+type Cell = (String,Tone);
+
+fn blank() -> Cell {
+    (String::new(),Tone::Plain)
+}
+
+/** The rows showing a value added to or removed from one side, with multi-line strings shown a line per row */
+fn value_rows(lav: &LocationAndValue, tone: Tone) -> Vec<Cell> {
+    if is_subtree(&lav.value) {
+        let mut cells = vec![(format!("{} =",lav.loc.path),tone)];
+        cells.extend(yaml_lines(&lav.value).into_iter().map(|line| (format!("  {}",line),tone)));
+        return cells
+    }
+    match lav.value.as_str().filter(|s| s.contains('\n')) {
+        Some(text) => {
+            let mut cells = vec![(format!("{} = |",lav.loc.path),tone)];
+            cells.extend(text.trim_end_matches('\n').split('\n').map(|line| (format!("  {}",line),tone)));
+            cells
+        }
+        None => vec![(format!("{} = {}",lav.loc.path,scalar_text(&lav.value)),tone)]
+    }
+}
+
+/**
+ * The rows showing the changes between two multi-line strings, with unchanged lines
+ * on both sides and each run of removed lines beside the lines that replaced them.
+ */
+fn patch_rows(old: &str, new: &str, rows: &mut Vec<(Cell,Cell)>) {
+    let patch = create_patch(old,new);
+    for hunk in patch.hunks() {
+        let (old_range,new_range) = (hunk.old_range(),hunk.new_range());
+        rows.push((
+            (format!("@@ -{},{}",old_range.start(),old_range.len()),Tone::Heading),
+            (format!("@@ +{},{}",new_range.start(),new_range.len()),Tone::Heading)
+        ));
+        let (mut removed,mut added) = (Vec::new(),Vec::new());
+        for line in hunk.lines() {
+            match line {
+                Line::Delete(text) => removed.push((format!("  {}",text.trim_end_matches('\n')),Tone::Removed)),
+                Line::Insert(text) => added.push((format!("  {}",text.trim_end_matches('\n')),Tone::Added)),
+                Line::Context(text) => {
+                    pair_up(&mut removed,&mut added,rows);
+                    let text = format!("  {}",text.trim_end_matches('\n'));
+                    rows.push(((text.clone(),Tone::Plain),(text,Tone::Plain)));
+                }
+            }
+        }
+        pair_up(&mut removed,&mut added,rows);
+    }
+}
+
+/** Add rows pairing cells on the left with those on the right, leaving the rest blank */
+fn pair_up(left: &mut Vec<Cell>, right: &mut Vec<Cell>, rows: &mut Vec<(Cell,Cell)>) {
+    let len = left.len().max(right.len());
+    left.resize(len,blank());
+    right.resize(len,blank());
+    rows.extend(left.drain(..).zip(right.drain(..)));
+}
+
+/** The rows showing the differences in a document */
+fn document_rows(document: &DocumentDiffs) -> Vec<(Cell,Cell)> {
+    let mut rows = Vec::new();
+    for diff in &document.diffs {
+        match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => pair_up(&mut Vec::new(),&mut value_rows(lav,Tone::Added),&mut rows),
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => pair_up(&mut value_rows(lav,Tone::Removed),&mut Vec::new(),&mut rows),
+            Diff::Differ(lav1,lav2) => match (lav1.value.as_str(),lav2.value.as_str()) {
+                (Some(old),Some(new)) if old.contains('\n') || new.contains('\n') => {
+                    rows.push(((format!("{} = |",lav1.loc.path),Tone::Removed),(format!("{} = |",lav2.loc.path),Tone::Added)));
+                    patch_rows(old,new,&mut rows);
+                }
+                _ => pair_up(&mut value_rows(lav1,Tone::Removed),&mut value_rows(lav2,Tone::Added),&mut rows)
+            },
+            Diff::Move(from,to) => {
+                let value = if matches!(*from.value,Yaml::Array(_)|Yaml::Hash(_)) { String::new() } else { format!(" = {}",scalar_text(&from.value)) };
+                rows.push(((format!("~ {}{}",from.loc.path,value),Tone::Moved),(format!("~ {}{}",to.loc.path,value),Tone::Moved)));
+            }
+            Diff::DocumentRenamed(loc1,loc2) => rows.push(((loc1.doc.to_string(),Tone::Moved),(loc2.doc.to_string(),Tone::Moved)))
+        }
+    }
+    rows
+}
+
+/** Split text into lines no wider than a column, breaking within words if necessary */
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()]
+    }
+    chars.chunks(width.max(1)).map(|chunk| chunk.iter().collect()).collect()
+}
+
+fn write_cell(out: &mut dyn io::Write, coloured: bool, text: &str, tone: Tone, width: Option<usize>) -> Result<()> {
+    let text = match width {
+        Some(width) => format!("{:<width$}",text,width = width),
+        None => text.to_string()
+    };
+    let text = match tone {
+        Tone::Plain => text.as_str().into(),
+        Tone::Removed => colorize_with(coloured,&text,ColouredStr::red),
+        Tone::Added => colorize_with(coloured,&text,ColouredStr::green),
+        Tone::Moved => colorize_with(coloured,&text,ColouredStr::yellow),
+        Tone::Heading => colorize_with(coloured,&text,ColouredStr::cyan)
+    };
+    write!(out,"{}",text)?;
+    Ok(())
+}
+
+fn write_row(out: &mut dyn io::Write, coloured: bool, column: usize, left: &Cell, right: &Cell) -> Result<()> {
+    let (lines1,lines2) = (wrap(&left.0,column),wrap(&right.0,column));
+    for n in 0..lines1.len().max(lines2.len()) {
+        write_cell(out,coloured,lines1.get(n).map_or("",|s| s.as_str()),left.1,Some(column))?;
+        write!(out,"{}",SEPARATOR)?;
+        write_cell(out,coloured,lines2.get(n).map_or("",|s| s.as_str()),right.1,None)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/**
+ * Write the differences in each document in two columns, those of the original file
+ * on the left and the modified file on the right, to fit the given width. Text too
+ * wide for its column is wrapped.
+ */
+pub fn write_side_by_side(out: &mut dyn io::Write, coloured: bool, width: usize, comparison: &Comparison, opts: &DiffOptions, diffs: &Diffs) -> Result<()> {
+    let column = (width.saturating_sub(SEPARATOR.chars().count()) / 2).max(1);
+    let rule = "─".repeat(column);
+    let mut first = true;
+    for document in comparison.document_diffs(diffs).iter().filter(|document| !document.diffs.is_empty()) {
+        if !first { writeln!(out)? }
+        first = false;
+        let status = match (document.original,document.modified) {
+            (Some(_),None) => " (removed)",
+            (None,Some(_)) => " (added)",
+            _ => ""
+        };
+        write_cell(out,coloured,&format!("{}{}",document,status),Tone::Moved,None)?;
+        writeln!(out)?;
+        write_row(out,coloured,column,&(opts.file1.clone(),Tone::Plain),&(opts.file2.clone(),Tone::Plain))?;
+        writeln!(out,"{}─┼─{}",rule,rule)?;
+        for (left,right) in document_rows(document) {
+            write_row(out,coloured,column,&left,&right)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn side_by_side(text1: &str, text2: &str, width: usize) -> String {
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let comparison = Comparison::new(&opts,&None,YamlLoader::load_from_str(text1).unwrap(),YamlLoader::load_from_str(text2).unwrap()).unwrap();
+        let diffs = comparison.diffs(&opts,&None).unwrap();
+        let mut out = Vec::new();
+        write_side_by_side(&mut out,false,width,&comparison,&opts,&diffs).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_side_by_side() {
+        assert_eq!(concat!(
+            "[0]\n",
+            "f1.yaml        │ f2.yaml\n",
+            "───────────────┼───────────────\n",
+            "a = 1          │ a = 2\n",
+            "b = a long val │ \n",
+            "ue             │ \n",
+            "               │ c[0] = x\n",
+            "\n",
+            "[1] (removed)\n",
+            "f1.yaml        │ f2.yaml\n",
+            "───────────────┼───────────────\n",
+            "d = 1          │ \n"),
+            side_by_side("a: 1\nb: a long value\n---\nd: 1\n","a: 2\nc: [x]\n",31));
+    }
+
+    #[test]
+    fn test_patch_rows() {
+        let mut rows = Vec::new();
+        patch_rows("l1\nl2\nl3\nl4\n","l1\nL2\nl3\nl4\nl5\n",&mut rows);
+        let text: Vec<(&str,&str)> = rows.iter().map(|((left,_),(right,_))| (left.as_str(),right.as_str())).collect();
+        assert_eq!(vec![
+            ("@@ -1,4","@@ +1,5"),
+            ("  l1","  l1"),
+            ("  l2","  L2"),
+            ("  l3","  l3"),
+            ("  l4","  l4"),
+            ("","  l5")
+        ],text);
+        assert_eq!((Tone::Removed,Tone::Added),((rows[2].0).1,(rows[2].1).1));
+    }
+}
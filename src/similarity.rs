@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use yaml_rust::Yaml;
+use crate::keypath::{ItemKey,KeyPath,scalar_string};
+
+/** Paths and values of the leaves of a document */
+pub struct Leaves {
+    paths: HashSet<String>,
+    values: HashSet<(String,String)>
+}
+
+impl Leaves {
+    pub fn new(y: &Yaml) -> Leaves {
+        let mut leaves = Leaves{paths: HashSet::new(), values: HashSet::new()};
+        leaves.collect(y,KeyPath::new());
+        leaves
+    }
+
+    fn collect(&mut self, y: &Yaml, path: KeyPath) {
+        match y {
+            Yaml::Hash(h) => {
+                for (k,v) in h {
+                    let key = scalar_string(k).unwrap_or_default();
+                    self.collect(v,path.push(ItemKey::Key(key)));
+                }
+            }
+            Yaml::Array(a) => {
+                for (i,v) in a.iter().enumerate() {
+                    self.collect(v,path.push(ItemKey::Index(i)));
+                }
+            }
+            _ => {
+                let pathstr = path.to_string();
+                let value = scalar_string(y).unwrap_or_default();
+                self.paths.insert(pathstr.clone());
+                self.values.insert((pathstr,value));
+            }
+        }
+    }
+
+    /** The similarity of the documents these are the leaves of, as for `similarity` */
+    pub fn similarity(&self, other: &Leaves) -> f64 {
+        let common = self.paths.intersection(&other.paths).count()
+            + self.values.intersection(&other.values).count();
+        let all = self.paths.union(&other.paths).count()
+            + self.values.union(&other.values).count();
+        if all == 0 { 1.0 } else { common as f64 / all as f64 }
+    }
+}
+
+/**
+ * Structural similarity of two documents, between 0 and 1. This is the proportion
+ * of leaf paths, and of leaf path and value pairs, that are common to both documents 
+ * out of all of those found in either.
+ */
+pub fn similarity(y1: &Yaml, y2: &Yaml) -> f64 {
+    Leaves::new(y1).similarity(&Leaves::new(y2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_similarity() {
+        let docs = YamlLoader::load_from_str(concat!(
+            "metadata: {name: vault1, labels: [a, b]}\n",
+            "---\n",
+            "metadata: {name: vault2, labels: [a, b]}\n",
+            "---\n",
+            "spec: {replicas: 3}\n"
+        )).unwrap();
+        assert_eq!(1.0,similarity(&docs[0],&docs[0]));
+        assert_eq!(5.0/7.0,similarity(&docs[0],&docs[1]));
+        assert_eq!(0.0,similarity(&docs[0],&docs[2]));
+    }
+}
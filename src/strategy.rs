@@ -1,7 +1,7 @@
 
 use serde::{Deserialize};
 use yaml_rust::Yaml;
-use crate::error::{Result};
+use crate::error::{ErrorKind,Result};
 use crate::keypath::{KeyPathFuncs,KeyPath};
 use regex::Regex;
 use std::cell::{Ref,RefCell};
@@ -12,7 +12,60 @@ pub struct Strategy {
     #[serde(default)]
     transform: Option<Transform>,
     #[serde(default)]
-    filter: Option<Filter>
+    filter: Option<Filter>,
+    #[serde(default)]
+    arrays: Vec<ArraySpec>,
+    #[serde(default,rename="match")]
+    document_match: Option<DocumentMatch>
+}
+
+#[derive(PartialEq,Clone,Deserialize,Debug)]
+struct DocumentMatch {
+    #[serde(default)]
+    keys: Vec<String>
+}
+
+/** How the elements of two arrays are paired up for comparison */
+#[derive(PartialEq,Clone,Debug)]
+pub enum ArrayMatch {
+    /** Elements are compared by index */
+    Position,
+    /** Elements are mappings, compared by the value of a key */
+    Key(String),
+    /** Elements are aligned by the longest common subsequence of the arrays */
+    Lcs,
+    /** Arrays are compared as multisets, ignoring the order of elements */
+    Unordered
+}
+
+#[derive(PartialEq,Clone,Deserialize,Debug)]
+#[serde(rename_all="lowercase")]
+enum ArrayCompare {
+    Position,
+    Lcs,
+    Unordered
+}
+
+#[derive(PartialEq,Clone,Deserialize,Debug)]
+#[serde(untagged)]
+enum ArrayPathRule {
+    Path {
+        path: String
+    },
+    Regex {
+        #[serde(flatten)]
+        regex: CachedRegex
+    }
+}
+
+#[derive(PartialEq,Clone,Deserialize,Debug)]
+struct ArraySpec {
+    #[serde(flatten)]
+    select: ArrayPathRule,
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    compare: Option<ArrayCompare>
 }
 
 
@@ -73,7 +126,7 @@ impl PartialEq for CachedRegex {
 }
 
 impl CachedRegex {
-    fn get_re(&self) -> Result<ReRef> {
+    fn get_re(&self) -> Result<ReRef<'_>> {
         {
             let mut bre = self.re.borrow_mut();
             if bre.is_none() {
@@ -279,11 +332,9 @@ impl TransformSpec {
         }
     }
     fn apply(&self, y: &mut Yaml) -> Result<()> {
-        if self.select(y)? {
-            if !self.apply_drop(y) {
-                self.apply_replace(y)?;
-                self.apply_set(y)?;
-            }
+        if self.select(y)? && !self.apply_drop(y) {
+            self.apply_replace(y)?;
+            self.apply_set(y)?;
         }
         Ok(())
     }
@@ -315,6 +366,26 @@ impl DocumentFilterRule {
     }
 }
 
+impl ArrayPathRule {
+    fn accept(&self, path: &KeyPath) -> Result<bool> {
+        match self {
+            ArrayPathRule::Path{path: rule_path} => Ok(path.unindexed().to_string() == *rule_path),
+            ArrayPathRule::Regex{regex} => Ok(regex.get_re()?.is_match(path.to_string().as_str()))
+        }
+    }
+}
+
+impl ArraySpec {
+    fn array_match(&self) -> ArrayMatch {
+        match (&self.key,&self.compare) {
+            (Some(key),_) => ArrayMatch::Key(key.clone()),
+            (None,Some(ArrayCompare::Lcs)) => ArrayMatch::Lcs,
+            (None,Some(ArrayCompare::Unordered)) => ArrayMatch::Unordered,
+            (None,_) => ArrayMatch::Position
+        }
+    }
+}
+
 fn include_exclude_filter<T,F: Fn(&T) -> Result<bool>>(include: &Vec<T>, exclude: &Vec<T>, predicate: F) -> Result<bool> {
     let mut accepted = include.is_empty();
     for item in include {
@@ -349,8 +420,16 @@ impl DocumentFilterSpec {
 }
 
 impl Strategy {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(text: &str) -> Result<Strategy> {
-        Ok(serde_yaml::from_str(&text)?)
+        let strategy: Strategy = serde_yaml::from_str(text)?;
+        if let Some(spec) = strategy.arrays.iter().find(|spec| spec.key.is_some() && spec.compare.is_some()) {
+            return Err(ErrorKind::InvalidStrategy(format!("the array rule for '{}' has both a key and a compare method",match &spec.select {
+                ArrayPathRule::Path{path} => path,
+                ArrayPathRule::Regex{regex} => &regex.regex
+            })).into())
+        }
+        Ok(strategy)
     }
     pub fn transform(&self,y: &mut Yaml, modified: bool) -> Result<()> {
         if let Some(transform) = &self.transform {
@@ -360,6 +439,10 @@ impl Strategy {
         }
         Ok(())
     }
+    /** Whether the strategy transforms documents at all */
+    pub fn has_transforms(&self) -> bool {
+        self.transform.as_ref().is_some_and(|t| !t.original.is_empty() || !t.modified.is_empty() || !t.both.is_empty())
+    }
     pub fn filter_accept(&self, path: &KeyPath) -> Result<bool> {
         match &self.filter {
             None => Ok(true),
@@ -369,6 +452,22 @@ impl Strategy {
             }
         }
     }
+    /** How elements of the array at the given path should be matched, if a rule covers it */
+    pub fn array_match(&self, path: &KeyPath) -> Result<Option<ArrayMatch>> {
+        for spec in &self.arrays {
+            if spec.select.accept(path)? {
+                return Ok(Some(spec.array_match()))
+            }
+        }
+        Ok(None)
+    }
+    /** Key paths whose values identify a document, if documents are matched by key */
+    pub fn document_keys(&self) -> Option<&[String]> {
+        match &self.document_match {
+            Some(document_match) if !document_match.keys.is_empty() => Some(&document_match.keys),
+            _ => None
+        }
+    }
     pub fn accept_document(&self, y: &Yaml) -> Result<bool> {
         match &self.filter {
             None => Ok(true),
@@ -442,6 +541,44 @@ mod test {
         }
     }   
 
+    #[test]
+    fn test_deserialize_arrays() {
+        let test_yaml = r#"
+        arrays:
+          - path: spec.template.spec.containers
+            key: name
+          - regex: \.ports$
+          - path: spec.template.spec.containers.args
+            compare: lcs
+          - path: metadata.finalizers
+            compare: unordered
+        "#;
+        let strategy = Strategy::from_str(test_yaml).map_err(|e| e.to_string()).unwrap();
+        assert_eq!(strategy.arrays.len(),4);
+        let containers = KeyPath::parse("spec.template.spec.containers");
+        let ports = KeyPath::parse("spec.template.spec.containers[name=app].ports");
+        let env = KeyPath::parse("spec.template.spec.containers[name=app].env");
+        assert_eq!(Some(ArrayMatch::Key("name".to_string())),strategy.array_match(&containers).unwrap());
+        assert_eq!(Some(ArrayMatch::Position),strategy.array_match(&ports).unwrap());
+        assert_eq!(None,strategy.array_match(&env).unwrap());
+        let args = KeyPath::parse("spec.template.spec.containers[0].args");
+        assert_eq!(Some(ArrayMatch::Lcs),strategy.array_match(&args).unwrap());
+        let finalizers = KeyPath::parse("metadata.finalizers");
+        assert_eq!(Some(ArrayMatch::Unordered),strategy.array_match(&finalizers).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_arrays_key_and_compare() {
+        let test_yaml = r#"
+        arrays:
+          - path: spec.template.spec.containers
+            key: name
+            compare: lcs
+        "#;
+        let error = Strategy::from_str(test_yaml).unwrap_err().to_string();
+        assert_eq!("invalid strategy: the array rule for 'spec.template.spec.containers' has both a key and a compare method",error);
+    }
+
     #[test]
     fn test_deserialize_document_filter() {
         let test_yaml = r#"
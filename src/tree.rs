@@ -0,0 +1,250 @@
+use std::io;
+use ansi_colors::ColouredStr;
+use diffy::{create_patch,Line};
+use yaml_rust::Yaml;
+use crate::error::Result;
+use crate::json::scalar_text;
+use crate::keypath::{ItemKey,KeyPath};
+use crate::yamldiff::{colorize,colorize_with,is_subtree,yaml_lines,Comparison,Diff,Diffs,LocationAndValue};
+
+/** A path component as a heading: a hash key as it appears in the YAML, or an array element in brackets */
+fn label(item: &ItemKey) -> String {
+    match item {
+        ItemKey::Key(key) => key.clone(),
+        _ => KeyPath(vec![item.clone()]).to_string()
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+/**
+ * Writes differences beneath the headings of their paths, writing only the headings
+ * that differ from those of the previous difference.
+ */
+struct TreeWriter<'o> {
+    out: &'o mut dyn io::Write,
+    coloured: bool,
+    headings: Vec<ItemKey>
+}
+
+impl<'o> TreeWriter<'o> {
+    /** Write any headings of the path not already written, returning the depth of the values beneath it */
+    fn headings(&mut self, parent: &[ItemKey]) -> Result<usize> {
+        let common = self.headings.iter().zip(parent).take_while(|(written,item)| written == item).count();
+        for (depth,item) in parent.iter().enumerate().skip(common) {
+            writeln!(self.out,"{}{}:",indent(depth+1),label(item))?;
+        }
+        self.headings = parent.to_vec();
+        Ok(parent.len()+1)
+    }
+
+    /** Write the heading of a document, which starts a new tree */
+    fn document(&mut self, heading: &str) -> Result<()> {
+        writeln!(self.out,"{}",colorize_with(self.coloured,heading,ColouredStr::yellow))?;
+        self.headings.clear();
+        Ok(())
+    }
+
+    fn line<'t>(&mut self, depth: usize, text: &'t str, colour: Option<fn(&mut ColouredStr<'t>)>) -> Result<()> {
+        match colour {
+            Some(colour) => writeln!(self.out,"{}{}",indent(depth),colorize_with(self.coloured,text,colour))?,
+            None => writeln!(self.out,"{}{}",indent(depth),text)?
+        }
+        Ok(())
+    }
+
+    /** Write a value added or removed, labelled with the last component of its path */
+    fn value(&mut self, depth: usize, label: &str, value: &Yaml, remove: bool) -> Result<()> {
+        let chevron = if remove {"<"} else {">"};
+        let label = if label.is_empty() { String::new() } else { format!("{}: ",label) };
+        if is_subtree(value) {
+            let message = format!("{} {}",chevron,label).trim_end().to_string();
+            writeln!(self.out,"{}{}",indent(depth),colorize(self.coloured,&message,remove))?;
+            for line in yaml_lines(value) {
+                writeln!(self.out,"{}{}",indent(depth+1),colorize(self.coloured,&line,remove))?;
+            }
+            return Ok(())
+        }
+        match value.as_str().filter(|s| s.contains('\n')) {
+            Some(text) => {
+                let message = format!("{} {}|",chevron,label);
+                writeln!(self.out,"{}{}",indent(depth),colorize(self.coloured,&message,remove))?;
+                for line in text.trim_end_matches('\n').split('\n') {
+                    writeln!(self.out,"{}{}",indent(depth+1),colorize(self.coloured,line,remove))?;
+                }
+            }
+            None => {
+                let message = format!("{} {}{}",chevron,label,scalar_text(value));
+                writeln!(self.out,"{}{}",indent(depth),colorize(self.coloured,&message,remove))?;
+            }
+        }
+        Ok(())
+    }
+
+    /** Write the changes between two multi-line strings as the hunks of a patch */
+    fn patch(&mut self, depth: usize, label: &str, old: &str, new: &str) -> Result<()> {
+        self.line(depth,&format!("{}: |",label),None)?;
+        for hunk in create_patch(old,new).hunks() {
+            self.line(depth+1,&format!("@@ -{} +{} @@",hunk.old_range(),hunk.new_range()),Some(ColouredStr::cyan))?;
+            for line in hunk.lines() {
+                match line {
+                    Line::Context(text) => self.line(depth+1,&format!(" {}",text.trim_end_matches('\n')),None)?,
+                    Line::Delete(text) => self.line(depth+1,&format!("-{}",text.trim_end_matches('\n')),Some(ColouredStr::red))?,
+                    Line::Insert(text) => self.line(depth+1,&format!("+{}",text.trim_end_matches('\n')),Some(ColouredStr::green))?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * The heading path and label of a value, or of the second of a pair of values, which is
+     * labelled with its full path if it isn't beneath the same headings as the first.
+     */
+    fn split<'p>(lav: &'p LocationAndValue, first: Option<&LocationAndValue>) -> (&'p [ItemKey],String) {
+        let path = &lav.loc.path.0;
+        let (parent,last) = match path.split_last() {
+            Some((last,parent)) => (parent,label(last)),
+            None => (&path[..],String::new())
+        };
+        match first.map(|first| &first.loc.path.0) {
+            Some(first) if first.split_last().map(|(_,parent)| parent) != Some(parent) => (parent,lav.loc.path.to_string()),
+            _ => (parent,last)
+        }
+    }
+
+    fn diff(&mut self, diff: &Diff) -> Result<()> {
+        match diff {
+            Diff::Add(lav) | Diff::Remove(lav) | Diff::AddEmpty(lav) | Diff::RemoveEmpty(lav) => {
+                let (parent,label) = TreeWriter::split(lav,None);
+                let depth = self.headings(parent)?;
+                self.value(depth,&label,&lav.value,matches!(diff,Diff::Remove(_)|Diff::RemoveEmpty(_)))?;
+            }
+            Diff::Differ(lav1,lav2) => {
+                let (parent,label1) = TreeWriter::split(lav1,None);
+                let (_,label2) = TreeWriter::split(lav2,Some(lav1));
+                let depth = self.headings(parent)?;
+                match (lav1.value.as_str(),lav2.value.as_str()) {
+                    (Some(old),Some(new)) if label1 == label2 && (old.contains('\n') || new.contains('\n')) => self.patch(depth,&label1,old,new)?,
+                    _ => {
+                        self.value(depth,&label1,&lav1.value,true)?;
+                        self.value(depth,&label2,&lav2.value,false)?;
+                    }
+                }
+            }
+            Diff::Move(from,to) => {
+                let (parent,label1) = TreeWriter::split(from,None);
+                let (_,label2) = TreeWriter::split(to,Some(from));
+                let depth = self.headings(parent)?;
+                let value = if matches!(*from.value,Yaml::Array(_)|Yaml::Hash(_)) { String::new() } else { format!(": {}",scalar_text(&from.value)) };
+                let target = if label1 == label2 { "(moved)".to_string() } else { format!("-> {}",label2) };
+                self.line(depth,&format!("~ {} {}{}",label1,target,value),Some(ColouredStr::yellow))?;
+            }
+            Diff::DocumentRenamed(loc1,loc2) => {
+                let message = format!("~ {}: {} -> {}: {}",loc1.fname,loc1.doc,loc2.fname,loc2.doc);
+                self.line(1,&message,Some(ColouredStr::yellow))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Write the differences in each document as a tree, with the keys and array elements
+ * containing them as nested headings, in the way the YAML itself is laid out.
+ */
+pub fn write_tree(out: &mut dyn io::Write, coloured: bool, comparison: &Comparison, diffs: &Diffs) -> Result<()> {
+    let mut writer = TreeWriter{out,coloured,headings: Vec::new()};
+    let mut first = true;
+    for document in comparison.document_diffs(diffs).iter().filter(|document| !document.diffs.is_empty()) {
+        if !first { writeln!(writer.out)? }
+        first = false;
+        let status = match (document.original,document.modified) {
+            (Some(_),None) => " (removed)",
+            (None,Some(_)) => " (added)",
+            _ => ""
+        };
+        writer.document(&format!("{}{}",document,status))?;
+        for diff in &document.diffs {
+            writer.diff(diff)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use yaml_rust::YamlLoader;
+    use crate::yamldiff::DiffOptions;
+
+    fn tree(opts: &DiffOptions, text1: &str, text2: &str) -> String {
+        let comparison = Comparison::new(opts,&None,YamlLoader::load_from_str(text1).unwrap(),YamlLoader::load_from_str(text2).unwrap()).unwrap();
+        let diffs = comparison.diffs(opts,&None).unwrap();
+        let mut out = Vec::new();
+        write_tree(&mut out,false,&comparison,&diffs).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_tree() {
+        let opts = DiffOptions{subtrees: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        assert_eq!(concat!(
+            "[0]\n",
+            "  metadata:\n",
+            "    labels:\n",
+            "      < app.kubernetes.io/instance: vault1\n",
+            "      > app.kubernetes.io/instance: vault2\n",
+            "      > tier: \"1\"\n",
+            "  spec:\n",
+            "    ports:\n",
+            "      [0]:\n",
+            "        < port: 80\n",
+            "        > port: 8080\n",
+            "\n",
+            "[1] (removed)\n",
+            "  <\n",
+            "    gone: true\n",
+            "    more:\n",
+            "      a:\n",
+            "        - 1\n",
+            "        - 2\n",
+            "      b:\n",
+            "        - c: 3\n"),
+            tree(&opts,
+                "metadata:\n  labels:\n    app.kubernetes.io/instance: vault1\nspec:\n  ports:\n  - port: 80\n---\ngone: true\nmore: {a: [1, 2], b: [{c: 3}]}\n",
+                "metadata:\n  labels:\n    app.kubernetes.io/instance: vault2\n    tier: '1'\nspec:\n  ports:\n  - port: 8080\n"));
+    }
+
+    #[test]
+    fn test_tree_patches_and_moves() {
+        let opts = DiffOptions{lcs: true, ..DiffOptions::new("f1.yaml","f2.yaml")};
+        assert_eq!(concat!(
+            "[0]\n",
+            "  config: |\n",
+            "    @@ -1,3 +1,3 @@\n",
+            "     a\n",
+            "    -b\n",
+            "    +B\n",
+            "     c\n",
+            "  list:\n",
+            "    ~ [0] -> [1]: p\n"),
+            tree(&opts,"config: \"a\\nb\\nc\\n\"\nlist: [p, q]\n","config: \"a\\nB\\nc\\n\"\nlist: [q, p]\n"));
+    }
+
+    #[test]
+    fn test_tree_renames() {
+        let opts = DiffOptions{k8s: true, similarity: Some(0.5), ..DiffOptions::new("f1.yaml","f2.yaml")};
+        assert_eq!(concat!(
+            "v1,ConfigMap,a -> v1,ConfigMap,b\n",
+            "  ~ f1.yaml: v1,ConfigMap,a -> f2.yaml: v1,ConfigMap,b\n",
+            "  metadata:\n",
+            "    < name: a\n",
+            "    > name: b\n"),
+            tree(&opts,
+                "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: a}\ndata: {x: '1', y: '2'}\n",
+                "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: b}\ndata: {x: '1', y: '2'}\n"));
+    }
+}
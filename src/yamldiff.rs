@@ -1,62 +1,54 @@
-extern crate yaml_rust;
-extern crate diffy;
-extern crate ansi_colors;
-extern crate linked_hash_map;
-extern crate regex;
-extern crate serde;
-extern crate serde_yaml;
-extern crate clap;
-
-pub use yaml_rust::{YamlLoader,Yaml,yaml};
-use clap::Parser;
+
+pub use yaml_rust::{YamlLoader,YamlEmitter,Yaml,yaml};
 use linked_hash_map::LinkedHashMap;
 use std::fmt::{Formatter,Display};
-use std::rc::Rc;
+use std::borrow::Cow;
+use std::sync::Arc;
 use std::cmp::max;
+use std::collections::{HashMap,HashSet,VecDeque};
 use std::{fs,fmt};
 use std::io;
 use std::io::{BufRead};
 use diffy::{create_patch,PatchFormatter};
 use ansi_colors::*;
 use regex::Regex;
-use crate::keypath::{ItemKey,KeyPath};
+use crate::keypath::{ItemKey,KeyPath,KeyPathFuncs,scalar_string};
+use crate::marks::{find_mark,mark_documents,Mark,Marks};
 use crate::error::{ErrorKind,Result,ResultExt};
-use crate:: strategy::Strategy;
-
-
-/* Command line options */
-#[derive(Parser)]
-pub struct Opts {
-    #[clap(help="Original YAML file")]
-    file1: String,
-    #[clap(help="Modified YAML file")]
-    file2: String,
-    #[clap(short,long,help="Compare kubernetes yaml documents")]
-    k8s: bool,
-    #[clap(short,long,help="Don't produce coloured output")]
-    no_colour: bool,
-    #[clap(short('x'),long,multiple_occurrences(true),help="Exclude YAML document paths matching regex")]
-    exclude: Vec<String>,
-    #[clap(short('f'),long,help="File name of strategy file")]
-    strategy: Option<String>,
-    #[clap(short('c'),long,help="Display the number of differences only, rather than the differences themselves")]
-    count: bool
-}
-
-impl Opts {
-    #[allow(dead_code)]
-    pub fn new() -> Opts {
-        Opts {
-            file1: String::new(), 
-            file2: String::new(), 
-            k8s: false, 
-            no_colour: false, 
-            exclude: vec![], 
-            strategy: None, 
-            count: false
-        }
-    }
-    
+use crate::strategy::{Strategy,ArrayMatch};
+use crate::k8s;
+use crate::similarity::Leaves;
+use crate::context::Context;
+
+
+/** Options controlling how documents are compared, independent of the command line */
+#[derive(Clone,Debug,Default)]
+pub struct DiffOptions {
+    /** Name of the original file, as reported in diff locations */
+    pub file1: String,
+    /** Name of the modified file, as reported in diff locations */
+    pub file2: String,
+    /** Match documents by Kubernetes group, version, kind, name and namespace */
+    pub k8s: bool,
+    /** Regular expressions of document paths to exclude from comparison */
+    pub exclude: Vec<String>,
+    /** Align arrays by longest common subsequence, unless the strategy says otherwise */
+    pub lcs: bool,
+    /** Don't report array elements that have changed position */
+    pub ignore_moves: bool,
+    /** Pair up otherwise unmatched documents that are at least this similar, from 0 to 1 */
+    pub similarity: Option<f64>,
+    /** Report a value found on only one side, or whose type has changed, as a whole rather than by its leaves */
+    pub subtrees: bool,
+    /** Don't distinguish null values and empty hashes and arrays from values that are missing */
+    pub empty_as_missing: bool
+}
+
+impl DiffOptions {
+    pub fn new(file1: &str, file2: &str) -> DiffOptions {
+        DiffOptions{file1: file1.to_string(), file2: file2.to_string(), ..DiffOptions::default()}
+    }
+
     fn exclude_regex(&self) -> Result<Vec<Regex>> {
         let mut result = Vec::<Regex>::new();
         for excl in &self.exclude {
@@ -64,22 +56,11 @@ impl Opts {
         }
         Ok(result)
     }
-    fn parse_strategy(&self) -> Result<Option<Strategy>> {
-        match &self.strategy {
-            None => Ok(None),
-            Some(fname) => Opts::parse_strategy_file(fname).chain_err(|| format!("can't load {}",fname))
-        }
-    }
-
-    fn parse_strategy_file(fname: &str) -> Result<Option<Strategy>> {
-        let yaml = fs::read_to_string(fname)?;
-        Ok(Some(Strategy::from_str(&yaml)?))
-    }
- }
+}
 
 
 /** A string struct that can hold either a borrowed reference or String value */
-enum LzyStr<'a> {
+pub(crate) enum LzyStr<'a> {
     Ref(&'a str),
     Val(String)
 }
@@ -109,10 +90,11 @@ impl<'a> Display for LzyStr<'a> {
 }
 
 /** Kubernetes metatdata - group, version and kind */
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq,Eq,Hash,Debug,Clone)]
-struct GVK {
-    api_version: String,
-    kind: String
+pub struct GVK {
+    pub api_version: String,
+    pub kind: String
 }
 
 impl Display for GVK {
@@ -123,10 +105,10 @@ impl Display for GVK {
 
 /** Kubernetes metadata - group, version kind plus name & namespace */
 #[derive(PartialEq,Eq,Hash,Debug,Clone)]
-struct K8SMeta {
-    grv: GVK,
-    name: String,
-    namespace: Option<String>
+pub struct K8SMeta {
+    pub grv: GVK,
+    pub name: String,
+    pub namespace: Option<String>
 }
 
 impl Display for K8SMeta {
@@ -143,109 +125,185 @@ impl Display for K8SMeta {
  * position or Kubernetes metadata 
  */
 #[derive(PartialEq,Eq,Hash,Debug,Clone)]
-enum DocKey {
+pub enum DocKey {
     Position(i32),
-    K8S(K8SMeta)
+    K8S(K8SMeta),
+    /** Values of the key paths given in the strategy's document match rule */
+    Properties(Vec<(String,String)>)
 }
 
 impl Display for DocKey {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             DocKey::Position(n) => write!(f,"[{}]",n),
-            DocKey::K8S(m)      => write!(f,"{}",m)
+            DocKey::K8S(m)      => write!(f,"{}",m),
+            DocKey::Properties(props) => {
+                let mut sep = "";
+                for (key,value) in props {
+                    write!(f,"{}{}={}",sep,key,value)?;
+                    sep = ",";
+                }
+                Ok(())
+            }
         }
     }
 }
 
-type Documents = LinkedHashMap<DocKey,Yaml>;
+pub type Documents = LinkedHashMap<DocKey,Yaml>;
+
+/** Shared name of a compared file, as reported in diff locations */
+pub type FileName = Arc<str>;
 
 #[derive(PartialEq,Clone,Debug)]
-struct Location<'a> {
-    fname: &'a str,
-    doc: Rc<DocKey>,
-    path: KeyPath,
+pub struct Location {
+    pub fname: FileName,
+    pub doc: Arc<DocKey>,
+    pub path: KeyPath,
+    /** Position of the value in the file, if the file was loaded with its positions */
+    pub mark: Option<Mark>
 }
 
-impl<'a> Display for Location<'a> {
+impl Display for Location {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let fname = if self.fname == "-" { "<STDIN>"} else { self.fname };
-        write!(f,"{}: {} {}",fname,self.doc,self.path)
+        let fname = if &*self.fname == "-" { "<STDIN>"} else { &self.fname };
+        match &self.mark {
+            Some(mark) => write!(f,"{}:{}: {}",fname,mark,self.doc)?,
+            None => write!(f,"{}: {}",fname,self.doc)?
+        }
+        if self.path.0.is_empty() { Ok(()) } else { write!(f," {}",self.path) }
     }
 }
 
-impl<'a> Location<'a> {
-    fn new(fname: &'a str, doc: Rc<DocKey>, path: KeyPath) -> Location<'a> {
-        Location{fname,doc,path}
+impl Location {
+    pub fn new(fname: FileName, doc: Arc<DocKey>, path: KeyPath) -> Location {
+        Location{fname,doc,path,mark: None}
     }
-    fn parent(&self) -> Option<Location<'a>> {
+    pub fn parent(&self) -> Option<Location> {
         if self.path.0.is_empty() {
             None
         } else {
             let mut newvec = self.path.0.clone();
             newvec.pop();
-            Some(Location{fname: self.fname, doc: self.doc.clone(), path: KeyPath(newvec)})
+            Some(Location::new(self.fname.clone(),self.doc.clone(),KeyPath(newvec)))
         }
     }
 }
 
+/**
+ * A value found at a location. The value is copied out of its document once, when the
+ * difference is found, and only shared after that, so cloning diffs doesn't copy it again.
+ */
 #[derive(Clone,Debug)]
-struct LocationAndValue<'a> {
-    loc: Location<'a>,
-    value: Yaml
+pub struct LocationAndValue {
+    pub loc: Location,
+    pub value: Arc<Yaml>
 }
 
-impl<'a> LocationAndValue<'a> {
-    fn new(fname: &'a str, doc: Rc<DocKey>, path: KeyPath, value: &Yaml) -> LocationAndValue<'a> {
-        LocationAndValue{loc: Location::new(fname,doc,path),value: value.clone()}
+impl LocationAndValue {
+    pub fn new(fname: FileName, doc: Arc<DocKey>, path: KeyPath, value: Arc<Yaml>) -> LocationAndValue {
+        LocationAndValue{loc: Location::new(fname,doc,path),value}
     }
 }
 
-impl<'a> Display for LocationAndValue<'a> {
+impl Display for LocationAndValue {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f,"{} = {:?}",self.loc,self.value)
     }
 }
 
 #[derive(Clone,Debug)]
-enum Diff<'a> {
-    Add(LocationAndValue<'a>),
-    Remove(LocationAndValue<'a>),
-    Differ(LocationAndValue<'a>,LocationAndValue<'a>)
+pub enum Diff {
+    Add(LocationAndValue),
+    Remove(LocationAndValue),
+    /** A null value or empty hash or array where the original has no value at all */
+    AddEmpty(LocationAndValue),
+    /** A null value or empty hash or array where the modified file has no value at all */
+    RemoveEmpty(LocationAndValue),
+    Differ(LocationAndValue,LocationAndValue),
+    /** An array element that has moved, from its original to its modified position */
+    Move(LocationAndValue,LocationAndValue),
+    /**
+     * A pair of matched documents whose keys differ, before any transformation. This heads
+     * the other differences found between the documents, is only reported if there are any,
+     * and isn't counted as a difference itself.
+     */
+    DocumentRenamed(Location,Location)
 }
 
-impl<'a> Diff<'a> {
-    fn add(fname: &'a str, doc: Rc<DocKey>, path: KeyPath, value: &Yaml) -> Diff<'a> {
-        Diff::Add(LocationAndValue::new(fname,doc,path,value))
+impl Diff {
+    fn add(fname: FileName, doc: Arc<DocKey>, path: KeyPath, value: &Yaml) -> Diff {
+        Diff::Add(LocationAndValue::new(fname,doc,path,Arc::new(value.clone())))
+    }
+    fn remove(fname: FileName, doc: Arc<DocKey>, path: KeyPath, value: &Yaml) -> Diff {
+        Diff::Remove(LocationAndValue::new(fname,doc,path,Arc::new(value.clone())))
+    }
+    fn add_empty(fname: FileName, doc: Arc<DocKey>, path: KeyPath, value: &Yaml) -> Diff {
+        Diff::AddEmpty(LocationAndValue::new(fname,doc,path,Arc::new(value.clone())))
+    }
+    fn remove_empty(fname: FileName, doc: Arc<DocKey>, path: KeyPath, value: &Yaml) -> Diff {
+        Diff::RemoveEmpty(LocationAndValue::new(fname,doc,path,Arc::new(value.clone())))
     }
-    fn remove(fname: &'a str, doc: Rc<DocKey>, path: KeyPath, value: &Yaml) -> Diff<'a> {
-        Diff::Remove(LocationAndValue::new(fname,doc,path,value))
+    fn differ(fname1: FileName, fname2: FileName, docs: DocPair, paths: PathPair, value1: &Yaml, value2: &Yaml) -> Diff {
+        Diff::Differ(
+            LocationAndValue::new(fname1,docs.original,paths.original,Arc::new(value1.clone())),
+            LocationAndValue::new(fname2,docs.modified,paths.modified,Arc::new(value2.clone()))
+        )
     }
-    fn differ(fname1: &'a str, fname2: &'a str, doc: Rc<DocKey>, path: KeyPath, value1: &Yaml, value2: &Yaml) -> Diff<'a> {
-        Diff::Differ(LocationAndValue::new(fname1,doc.clone(),path.clone(),value1),LocationAndValue::new(fname2,doc,path,value2))
+    fn moved(fname1: FileName, fname2: FileName, docs: DocPair, paths: PathPair, value: &Yaml) -> Diff {
+        let value = Arc::new(value.clone());
+        Diff::Move(
+            LocationAndValue::new(fname1,docs.original,paths.original,value.clone()),
+            LocationAndValue::new(fname2,docs.modified,paths.modified,value)
+        )
     }
-    #[allow(dead_code)]
-    fn key_path(&self) -> &KeyPath {
+    fn renamed(fname1: FileName, fname2: FileName, docs: DocPair) -> Diff {
+        Diff::DocumentRenamed(
+            Location::new(fname1,docs.original,KeyPath::new()),
+            Location::new(fname2,docs.modified,KeyPath::new())
+        )
+    }
+    pub fn key_path(&self) -> &KeyPath {
         match self {
             Diff::Add(lav) => &lav.loc.path,
             Diff::Remove(lav) => &lav.loc.path,
+            Diff::AddEmpty(lav) | Diff::RemoveEmpty(lav) => &lav.loc.path,
             Diff::Differ(lav1,_) => &lav1.loc.path,
+            Diff::Move(lav1,_) => &lav1.loc.path,
+            Diff::DocumentRenamed(loc1,_) => &loc1.path,
+        }
+    }
+    /** True if this is a difference between the documents with the given original and modified keys */
+    pub fn is_between(&self, original: &DocKey, modified: &DocKey) -> bool {
+        match self {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => *lav.loc.doc == *modified,
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => *lav.loc.doc == *original,
+            Diff::Differ(lav1,lav2) | Diff::Move(lav1,lav2) => *lav1.loc.doc == *original && *lav2.loc.doc == *modified,
+            Diff::DocumentRenamed(_,_) => false
         }
     }
 }
 
-fn load_file(fname: &str) -> Result<Vec<Yaml>> {
-    let yaml_text = 
-        if fname == "-" {
-            let mut buffer = String::new();
-            for line in io::stdin().lock().lines() {
-                buffer.push_str(&line.chain_err(|| "while reading standard input")?);
-                buffer.push_str("\n")
-            }
-            buffer
-        } else {
-            fs::read_to_string(fname).chain_err(|| format!("while reading {}",fname))?
-        };
-    Ok(YamlLoader::load_from_str(&yaml_text)?)
+fn read_file(fname: &str) -> Result<String> {
+    if fname == "-" {
+        let mut buffer = String::new();
+        for line in io::stdin().lock().lines() {
+            buffer.push_str(&line.chain_err(|| "while reading standard input")?);
+            buffer.push('\n')
+        }
+        Ok(buffer)
+    } else {
+        Ok(fs::read_to_string(fname).chain_err(|| format!("while reading {}",fname))?)
+    }
+}
+
+pub fn load_file(fname: &str) -> Result<Vec<Yaml>> {
+    Ok(YamlLoader::load_from_str(&read_file(fname)?)?)
+}
+
+/** Load the documents of a file, along with the position of each value in the file */
+pub fn load_file_marked(fname: &str) -> Result<(Vec<Yaml>,Vec<Marks>)> {
+    let yaml_text = read_file(fname)?;
+    Ok((YamlLoader::load_from_str(&yaml_text)?,mark_documents(&yaml_text)?))
 }
 
 
@@ -264,64 +322,185 @@ impl YamlFuncs for Yaml {
         Ok(self.str_result(key)?.to_string())
     }
     fn is_hash(&self) -> bool {
-        match self {
-            Yaml::Hash(_) => true,
-            _ =>             false
-        }
+        matches!(self,Yaml::Hash(_))
     }
 }
 
-fn index(docs: Vec<Yaml>, opts: &Opts, strategy: &Option<Strategy>) -> Result<Documents> {
-    let mut result = Documents::new();
-    if opts.k8s {
-        for mut yaml in docs {
-            if yaml.is_null() { continue; }
-            if let Some(s) = strategy {
-                if  !s.accept_document(&yaml)? {
-                    continue;
-                }
-            }
-            let api_version = yaml.string_result("apiVersion")?;
-            let kind = yaml.string_result("kind")?;
-            let name = yaml["metadata"].string_result("name")?;
+fn accept_document(yaml: &Yaml, strategy: &Option<Strategy>) -> Result<bool> {
+    match strategy {
+        Some(s) => s.accept_document(yaml),
+        None => Ok(true)
+    }
+}
+
+/** The values of a set of key paths in a document, for use as a document key */
+fn property_values(yaml: &Yaml, keys: &[String]) -> Result<Vec<(String,String)>> {
+    let mut result = Vec::new();
+    for key in keys {
+        let value = scalar_string(yaml.get_at_path(key.as_str())?)
+            .ok_or_else(|| ErrorKind::WrongType(key.clone()))?;
+        result.push((key.clone(),value));
+    }
+    Ok(result)
+}
+
+/** The key identifying a document, given its position in its file */
+pub(crate) fn document_key(yaml: &Yaml, position: usize, opts: &DiffOptions, strategy: &Option<Strategy>) -> Result<DocKey> {
+    if let Some(keys) = strategy.as_ref().and_then(|s| s.document_keys()) {
+        Ok(DocKey::Properties(property_values(yaml,keys)?))
+    } else if opts.k8s {
+        let api_version = yaml.string_result("apiVersion")?;
+        let kind = yaml.string_result("kind")?;
+        let name = yaml["metadata"].string_result("name")?;
+        let namespace = yaml["metadata"]["namespace"].as_str().map(String::from);
+        Ok(DocKey::K8S(K8SMeta{name,namespace,grv:GVK{api_version,kind}}))
+    } else {
+        Ok(DocKey::Position(position as i32))
+    }
+}
+
+pub fn index(docs: Vec<Yaml>, opts: &DiffOptions, strategy: &Option<Strategy>) -> Result<Documents> {
+    Ok(index_positions(docs,opts,strategy)?.docs)
+}
+
+/** Index documents, along with the position in its file of each document indexed */
+fn index_positions(docs: Vec<Yaml>, opts: &DiffOptions, strategy: &Option<Strategy>) -> Result<Indexed> {
+    let mut result = Indexed{docs: Documents::new(), positions: HashMap::new(), names: HashMap::new()};
+    let keyed = strategy.as_ref().and_then(|s| s.document_keys()).is_some();
+    for (position,mut yaml) in docs.into_iter().enumerate() {
+        if (keyed || opts.k8s) && (yaml.is_null() || !accept_document(&yaml,strategy)?) { continue; }
+        let key = document_key(&yaml,position,opts,strategy)?;
+        if keyed && result.docs.contains_key(&key) {
+            return Err(ErrorKind::DuplicateDocument(key.to_string()).into())
+        }
+        if opts.k8s && !keyed {
             if let Yaml::Hash(ref mut md) = &mut yaml {
-                md.insert(Yaml::String("name".to_string()),Yaml::String("myvalue".to_string()));
+                let name = Yaml::String("name".to_string());
+                let replaced = md.iter().position(|(k,_)| *k == name).map(|i| (i,md[&name].clone()));
+                md.insert(name,Yaml::String("myvalue".to_string()));
+                result.names.insert(key.clone(),replaced);
             }
-            let namespace = yaml["metadata"]["namespace"].as_str().map(String::from);
-            let key = DocKey::K8S(K8SMeta{name,namespace,grv:GVK{api_version,kind}});
-            result.insert(key,yaml);
-        }
-    } else {
-        let mut index = 0;
-        for yaml in docs {
-            result.insert(DocKey::Position(index),yaml);
-            index+=1;
         }
+        result.positions.insert(key.clone(),position);
+        result.docs.insert(key,yaml);
     }
     Ok(result)
 }
 
-type Diffs<'a> = Vec<Diff<'a>>;
+/** 
+ * Documents as indexed, with the position of each in its file and, for each document given
+ * a `name` when it was indexed, the position and value of any `name` it had before.
+ */
+struct Indexed {
+    docs: Documents,
+    positions: HashMap<DocKey,usize>,
+    names: HashMap<DocKey,Option<(usize,Yaml)>>
+}
+
+/** A copy of an indexed document with the `name` it had before it was indexed */
+fn unindexed(doc: &Yaml, replaced: &Option<(usize,Yaml)>) -> Yaml {
+    let mut hash = match doc {
+        Yaml::Hash(hash) => hash.clone(),
+        _ => return doc.clone()
+    };
+    let name = Yaml::String("name".to_string());
+    hash.remove(&name);
+    if let Some((position,value)) = replaced {
+        let mut entries: Vec<(Yaml,Yaml)> = hash.into_iter().collect();
+        entries.insert(*position,(name,value.clone()));
+        hash = entries.into_iter().collect();
+    }
+    Yaml::Hash(hash)
+}
+
+/** 
+ * The keys that documents had before being transformed, indexed by their keys 
+ * after transformation, for those documents whose key was changed.
+ */
+pub type Renames = HashMap<DocKey,DocKey>;
 
-struct DiffStats {
-    total: usize,
-    removals: usize,
-    additions: usize,
-    changes: usize
+fn find_renames(before: &[Option<DocKey>], after: &[Yaml], opts: &DiffOptions, strategy: &Option<Strategy>) -> Renames {
+    let mut result = Renames::new();
+    for (position,(key_before,y_after)) in before.iter().zip(after).enumerate() {
+        let key_after = document_key(y_after,position,opts,strategy);
+        if let (Some(key_before),Ok(key_after)) = (key_before,key_after) {
+            if *key_before != key_after {
+                result.insert(key_after,key_before.clone());
+            }
+        }
+    }
+    result
+}
+
+pub type Diffs = Vec<Diff>;
+
+pub struct DiffStats {
+    pub total: usize,
+    pub removals: usize,
+    pub additions: usize,
+    pub changes: usize,
+    pub moves: usize,
+    /** Renamed documents with differences, which aren't counted in the total */
+    pub renames: usize,
+    /** Null values and empty hashes and arrays added or removed */
+    pub empties: usize
+}
+
+/**
+ * The number of scalar values, and of null values and empty hashes and arrays, within a
+ * value. Empty values are only counted if they aren't treated as missing.
+ */
+fn leaf_count(value: &Yaml, empty_as_missing: bool) -> (usize,usize) {
+    let sum = |counts: Vec<(usize,usize)>| counts.into_iter().fold((0,0),|(v1,e1),(v2,e2)| (v1+v2,e1+e2));
+    match value {
+        Yaml::Hash(hash) if !hash.is_empty() => sum(hash.values().map(|v| leaf_count(v,empty_as_missing)).collect()),
+        Yaml::Array(arr) if !arr.is_empty() => sum(arr.iter().map(|v| leaf_count(v,empty_as_missing)).collect()),
+        _ if is_empty_value(value) => (0,if empty_as_missing {0} else {1}),
+        _ => (1,0)
+    }
 }
 
 impl DiffStats {
-    fn from<'a> (diffs: &Diffs<'a>) -> DiffStats {
+    pub fn from(diffs: &Diffs) -> DiffStats {
+        DiffStats::weighted(diffs,None)
+    }
+
+    /**
+     * The totals of the differences, counting a hash or array added or removed as a whole
+     * by the values within it, as if it had been compared by its leaves with the options
+     * it was found with.
+     */
+    pub fn from_leaves(diffs: &Diffs, opts: &DiffOptions) -> DiffStats {
+        DiffStats::weighted(diffs,Some(opts.empty_as_missing))
+    }
+
+    fn weighted(diffs: &Diffs, leaves: Option<bool>) -> DiffStats {
         let mut result = DiffStats { 
-            total: diffs.len(), removals: 0, additions: 0, changes: 0 
+            total: 0, removals: 0, additions: 0, changes: 0, moves: 0, renames: 0, empties: 0
+        };
+        let weight = |value: &Yaml| match leaves {
+            Some(empty_as_missing) => leaf_count(value,empty_as_missing),
+            None => (1,0)
         };
         for diff in diffs {
             match diff {
-                Diff::Remove(_) => result.removals += 1,
-                Diff::Add(_) => result.additions += 1,
-                Diff::Differ(_, _) => result.changes += 1
+                Diff::Remove(lav) => {
+                    let (values,empties) = weight(&lav.value);
+                    result.removals += values;
+                    result.empties += empties;
+                }
+                Diff::Add(lav) => {
+                    let (values,empties) = weight(&lav.value);
+                    result.additions += values;
+                    result.empties += empties;
+                }
+                Diff::AddEmpty(_) | Diff::RemoveEmpty(_) => result.empties += 1,
+                Diff::Differ(_, _) => result.changes += 1,
+                Diff::Move(_, _) => result.moves += 1,
+                Diff::DocumentRenamed(_, _) => result.renames += 1
             }
         }
+        result.total = result.removals + result.additions + result.changes + result.moves + result.empties;
         result
     }
 }
@@ -346,17 +525,30 @@ impl Display for DiffStats {
             write_value("additions",self.additions)?;
             write_value("removals", self.removals)?;
             write_value("changes", self.changes)?;
+            write_value("moves", self.moves)?;
+            write_value("empty values", self.empties)?;
             write!(f,")")?;
         }
+        match self.renames {
+            0 => (),
+            1 => write!(f,", 1 document renamed")?,
+            n => write!(f,", {} documents renamed",n)?
+        }
         Ok(())
     }
 }
 
-struct DiffContext<'a,'b> {
-    opts: &'a Opts,
-    path_filter: &'b PathFilter<'b>,
-    dockey: Option<Rc<DocKey>>,
-    diffs: Diffs<'a>
+struct DiffContext<'a> {
+    file1: FileName,
+    file2: FileName,
+    strategy: &'a Option<Strategy>,
+    array_default: ArrayMatch,
+    ignore_moves: bool,
+    subtrees: bool,
+    empty_as_missing: bool,
+    path_filter: &'a PathFilter<'a>,
+    docs: Option<DocPair>,
+    diffs: Diffs
 }
 
 struct PathFilter<'a> {
@@ -385,100 +577,557 @@ impl<'a> PathFilter<'a> {
                 return Ok(false)
             }
         }
-        return Ok(true)
+        Ok(true)
+    }
+}
+
+/**
+ * How the elements of the array at a path in a document are aligned with those of the
+ * array it's compared with: as given by the strategy, by the merge key of a Kubernetes
+ * resource's array, or else by default.
+ */
+fn array_match(strategy: &Option<Strategy>, doc: Option<&DocKey>, default: &ArrayMatch, path: &KeyPath) -> Result<ArrayMatch> {
+    if let Some(strategy) = strategy {
+        if let Some(array_match) = strategy.array_match(path)? {
+            return Ok(array_match)
+        }
+    }
+    if let Some(DocKey::K8S(meta)) = doc {
+        if let Some(key) = k8s::merge_key(&meta.grv.kind,path) {
+            return Ok(ArrayMatch::Key(key.to_string()))
+        }
+    }
+    Ok(default.clone())
+}
+
+impl<'a> DiffContext<'a> {
+    fn array_match(&self, path: &KeyPath) -> Result<ArrayMatch> {
+        array_match(self.strategy,self.docs.as_ref().map(|docs| &*docs.original),&self.array_default,path)
+    }
+    /**
+     * True if a value is missing, which is how a key or element absent from one side is
+     * compared, or is null or empty and being treated as missing.
+     */
+    fn missing(&self, value: &Yaml) -> bool {
+        matches!(value,Yaml::BadValue) || (self.empty_as_missing && is_empty_value(value))
+    }
+    fn push_move(&mut self, paths: PathPair, value: &Yaml) -> Result<()> {
+        if !self.ignore_moves && self.path_filter.accept(&paths.original)? {
+            self.diffs.push(Diff::moved(self.file1.clone(),self.file2.clone(),self.docs.clone().unwrap(),paths,value))
+        }
+        Ok(())
     }
 }
 
-fn recurse_array_diffs<'a,'b>(ctx: &mut DiffContext<'a,'b>, path: KeyPath, y1: &Yaml, y2: &Yaml) -> Result<()> {
+/** 
+ * The paths of the values being compared in the original and modified documents. 
+ * These differ only when array elements are aligned other than by position.
+ */
+#[derive(Clone,Debug)]
+struct PathPair {
+    original: KeyPath,
+    modified: KeyPath
+}
+
+impl PathPair {
+    fn new() -> PathPair {
+        PathPair{original: KeyPath::new(), modified: KeyPath::new()}
+    }
+    fn push(&self, key: ItemKey) -> PathPair {
+        PathPair{original: self.original.push(key.clone()), modified: self.modified.push(key)}
+    }
+    fn push_each(&self, key1: ItemKey, key2: ItemKey) -> PathPair {
+        PathPair{original: self.original.push(key1), modified: self.modified.push(key2)}
+    }
+}
+
+/** 
+ * The keys of a pair of documents being compared. These differ only when documents
+ * have been paired by similarity.
+ */
+#[derive(Clone,Debug)]
+struct DocPair {
+    original: Arc<DocKey>,
+    modified: Arc<DocKey>
+}
+
+impl DocPair {
+    fn new(original: &DocKey, modified: &DocKey) -> DocPair {
+        let original = Arc::new(original.clone());
+        let modified = if original.as_ref() == modified { original.clone() } else { Arc::new(modified.clone()) };
+        DocPair{original,modified}
+    }
+}
+
+/**
+ * Pair up documents found in only one of the two sets by their similarity, most similar 
+ * first, provided they are at least as similar as the threshold. Kubernetes documents 
+ * are only paired with documents of the same group, version and kind.
+ */
+fn pair_similar(d1: &Documents, d2: &Documents, threshold: Option<f64>) -> LinkedHashMap<DocKey,DocKey> {
+    let mut result = LinkedHashMap::new();
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => return result
+    };
+    let unmatched1: Vec<&DocKey> = d1.keys().filter(|key| !d2.contains_key(key)).collect();
+    let unmatched2: Vec<&DocKey> = d2.keys().filter(|key| !d1.contains_key(key)).collect();
+    let leaves1: Vec<Leaves> = unmatched1.iter().map(|key| Leaves::new(&d1[*key])).collect();
+    let leaves2: Vec<Leaves> = unmatched2.iter().map(|key| Leaves::new(&d2[*key])).collect();
+    let mut candidates = Vec::new();
+    for (i,key1) in unmatched1.iter().enumerate() {
+        for (j,key2) in unmatched2.iter().enumerate() {
+            if let (DocKey::K8S(meta1),DocKey::K8S(meta2)) = (key1,key2) {
+                if meta1.grv != meta2.grv { continue; }
+            }
+            let score = leaves1[i].similarity(&leaves2[j]);
+            if score >= threshold {
+                candidates.push((score,i,j));
+            }
+        }
+    }
+    candidates.sort_by(|(score1,i1,j1),(score2,i2,j2)| score2.total_cmp(score1).then(i1.cmp(i2)).then(j1.cmp(j2)));
+    let mut paired1 = vec![false; unmatched1.len()];
+    let mut paired2 = vec![false; unmatched2.len()];
+    for (_,i,j) in candidates {
+        if !paired1[i] && !paired2[j] {
+            paired1[i] = true;
+            paired2[j] = true;
+            result.insert(unmatched1[i].clone(),unmatched2[j].clone());
+        }
+    }
+    result
+}
+
+/** 
+ * Index the elements of an array by the value of a key. Returns None if any 
+ * element lacks a scalar value for the key, or if any value is repeated.
+ */
+fn keyed_elements<'y>(arr: &'y [Yaml], key: &str) -> Option<LinkedHashMap<String,&'y Yaml>> {
+    let mut result = LinkedHashMap::new();
+    for item in arr {
+        let value = scalar_string(&item[key])?;
+        if result.insert(value,item).is_some() {
+            return None
+        }
+    }
+    Some(result)
+}
+
+/**
+ * Align the elements of two arrays around pairs of equal elements, in order. Elements
+ * between them are paired by position if `pair_gaps` is true.
+ */
+fn align_around(anchors: Vec<(usize,usize)>, len1: usize, len2: usize, pair_gaps: bool) -> Vec<(Option<usize>,Option<usize>)> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0,0);
+    for (anchor1,anchor2) in anchors.into_iter().chain(std::iter::once((len1,len2))) {
+        while pair_gaps && i < anchor1 && j < anchor2 {
+            result.push((Some(i),Some(j)));
+            i += 1;
+            j += 1;
+        }
+        result.extend((i..anchor1).map(|i| (Some(i),None)));
+        result.extend((j..anchor2).map(|j| (None,Some(j))));
+        if anchor1 < len1 {
+            result.push((Some(anchor1),Some(anchor2)));
+        }
+        i = anchor1 + 1;
+        j = anchor2 + 1;
+    }
+    result
+}
+
+/** Indices of a pair of aligned array elements, either of which may be absent, and the path component identifying them */
+pub(crate) type AlignedElements = (Option<usize>,Option<usize>,ItemKey);
+
+/**
+ * The elements of the arrays at a path in a pair of documents, aligned in the order they'd
+ * be shown side by side, with the path component that identifies each pair when comparing
+ * them. Elements are paired as they're compared, except that elements that have moved are
+ * shown in their original and modified positions separately.
+ */
+pub(crate) fn align_arrays(opts: &DiffOptions, strategy: &Option<Strategy>, doc: &DocKey, path: &KeyPath, arr1: &[Yaml], arr2: &[Yaml])
+        -> Result<Vec<AlignedElements>> {
+    let default = if opts.lcs { ArrayMatch::Lcs } else { ArrayMatch::Position };
+    let aligned = match array_match(strategy,Some(doc),&default,path)? {
+        ArrayMatch::Key(key) => {
+            if let (Some(keyed1),Some(keyed2)) = (keyed_elements(arr1,&key),keyed_elements(arr2,&key)) {
+                let keys1: Vec<Yaml> = keyed1.keys().map(|k| Yaml::String(k.clone())).collect();
+                let keys2: Vec<Yaml> = keyed2.keys().map(|k| Yaml::String(k.clone())).collect();
+                let aligned = align_around(lcs_pairs(&keys1,&keys2),arr1.len(),arr2.len(),false);
+                return Ok(aligned.into_iter().map(|(i,j)| {
+                    let value = i.map(|i| &keys1[i]).or(j.map(|j| &keys2[j])).and_then(|k| k.as_str()).unwrap_or_default();
+                    (i,j,ItemKey::Match(key.clone(),value.to_string()))
+                }).collect());
+            }
+            align_around(Vec::new(),arr1.len(),arr2.len(),true)
+        }
+        ArrayMatch::Lcs | ArrayMatch::Unordered => align_around(lcs_pairs(arr1,arr2),arr1.len(),arr2.len(),true),
+        ArrayMatch::Position => align_around(Vec::new(),arr1.len(),arr2.len(),true)
+    };
+    Ok(aligned.into_iter().map(|(i,j)| (i,j,ItemKey::Index(i.or(j).unwrap_or_default()))).collect())
+}
+
+/** Flags marking the members of a longest strictly increasing subsequence of values */
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // lengths[i] is the length of the longest increasing subsequence ending at i
+    let mut lengths = vec![1usize; values.len()];
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+    for i in 0..values.len() {
+        for j in 0..i {
+            if values[j] < values[i] && lengths[j] + 1 > lengths[i] {
+                lengths[i] = lengths[j] + 1;
+                previous[i] = Some(j);
+            }
+        }
+    }
+    let mut result = vec![false; values.len()];
+    let mut current = (0..values.len()).max_by_key(|i| lengths[*i]);
+    while let Some(i) = current {
+        result[i] = true;
+        current = previous[i];
+    }
+    result
+}
+
+/**
+ * Compare arrays whose elements are matched by a key. Elements present in both 
+ * arrays whose relative order has changed are reported as moves, in addition to any 
+ * differences in their content.
+ */
+fn recurse_keyed_array_diffs(ctx: &mut DiffContext, paths: PathPair, key: &str, 
+                             keyed1: &LinkedHashMap<String,&Yaml>, keyed2: &LinkedHashMap<String,&Yaml>) -> Result<()> {
+    let absent = Yaml::BadValue;
+    let positions2: HashMap<&String,usize> = keyed2.keys().enumerate().map(|(j,value)| (value,j)).collect();
+    let paired: Vec<usize> = keyed1.keys().filter_map(|value| positions2.get(value).copied()).collect();
+    let mut in_order = longest_increasing(&paired).into_iter();
+    for (value,v1) in keyed1 {
+        let element = paths.push(ItemKey::Match(key.to_string(),value.clone()));
+        let v2 = match positions2.get(value) {
+            Some(_) => {
+                if !in_order.next().unwrap_or(true) {
+                    ctx.push_move(element.clone(),v1)?;
+                }
+                keyed2[value]
+            }
+            None => &absent
+        };
+        recurse_diffs(ctx, element, v1, v2)?;
+    }
+    for (value,v2) in keyed2 {
+        if !keyed1.contains_key(value) {
+            recurse_diffs(ctx, paths.push(ItemKey::Match(key.to_string(),value.clone())), &absent, v2)?;
+        }
+    }
+    Ok(())
+}
+
+/** 
+ * Find the longest common subsequence of two arrays, as a list of the index pairs 
+ * of equal elements, in order.
+ */
+fn lcs_pairs(arr1: &[Yaml], arr2: &[Yaml]) -> Vec<(usize,usize)> {
+    let (n,m) = (arr1.len(),arr2.len());
+    // lengths[i][j] is the LCS length of arr1[i..] and arr2[j..]
+    let mut lengths = vec![vec![0usize; m+1]; n+1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if arr1[i] == arr2[j] {
+                lengths[i+1][j+1] + 1
+            } else {
+                max(lengths[i+1][j],lengths[i][j+1])
+            }
+        }
+    }
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0,0);
+    while i < n && j < m {
+        if arr1[i] == arr2[j] {
+            result.push((i,j));
+            i += 1;
+            j += 1;
+        } else if lengths[i+1][j] >= lengths[i][j+1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+/**
+ * Compare arrays aligned by their longest common subsequence. Unaligned elements 
+ * equal to an unaligned element of the other array are reported as moves. Remaining 
+ * elements between aligned elements are compared with each other by position within 
+ * the gap, with any surplus reported as removals or additions at their own index.
+ */
+fn recurse_lcs_array_diffs(ctx: &mut DiffContext, paths: PathPair, arr1: &[Yaml], arr2: &[Yaml]) -> Result<()> {
+    let absent = Yaml::BadValue;
+    let mut anchors = lcs_pairs(arr1,arr2);
+    let mut moved1: Vec<Option<usize>> = vec![None; arr1.len()];
+    let mut moved2 = vec![false; arr2.len()];
+    for &(i,j) in &anchors {
+        moved1[i] = Some(j);
+        moved2[j] = true;
+    }
+    let aligned1: Vec<bool> = moved1.iter().map(Option::is_some).collect();
+    for (i,v1) in arr1.iter().enumerate().filter(|(i,_)| !aligned1[*i]) {
+        let found = arr2.iter().enumerate().position(|(j,v2)| !moved2[j] && v1 == v2);
+        if let Some(j) = found {
+            moved1[i] = Some(j);
+            moved2[j] = true;
+        }
+    }
+    anchors.push((arr1.len(),arr2.len()));
+    let (mut i, mut j) = (0,0);
+    for (anchor1,anchor2) in anchors {
+        let mut gap1 = Vec::new();
+        for (i,v1) in arr1.iter().enumerate().take(anchor1).skip(i) {
+            match moved1[i] {
+                Some(j) => ctx.push_move(paths.push_each(ItemKey::Index(i),ItemKey::Index(j)),v1)?,
+                None => gap1.push(i)
+            }
+        }
+        let gap2: Vec<usize> = (j..anchor2).filter(|j| !moved2[*j]).collect();
+        for (&i,&j) in gap1.iter().zip(gap2.iter()) {
+            recurse_diffs(ctx, paths.push_each(ItemKey::Index(i),ItemKey::Index(j)), &arr1[i], &arr2[j])?;
+        }
+        for &i in gap1.iter().skip(gap2.len()) {
+            recurse_diffs(ctx, paths.push(ItemKey::Index(i)), &arr1[i], &absent)?;
+        }
+        for &j in gap2.iter().skip(gap1.len()) {
+            recurse_diffs(ctx, paths.push(ItemKey::Index(j)), &absent, &arr2[j])?;
+        }
+        i = anchor1 + 1;
+        j = anchor2 + 1;
+    }
+    Ok(())
+}
+
+/**
+ * Compare arrays as multisets. Each element is paired with an equal element of the
+ * other array, if one remains unpaired, and only elements left over on either side
+ * are reported.
+ */
+fn recurse_unordered_array_diffs(ctx: &mut DiffContext, paths: PathPair, arr1: &[Yaml], arr2: &[Yaml]) -> Result<()> {
+    let absent = Yaml::BadValue;
+    let mut unpaired2 = HashMap::<&Yaml,VecDeque<usize>>::new();
+    for (j,v2) in arr2.iter().enumerate() {
+        unpaired2.entry(v2).or_default().push_back(j);
+    }
+    for (i,v1) in arr1.iter().enumerate() {
+        let paired = unpaired2.get_mut(v1).and_then(|indices| indices.pop_front());
+        if paired.is_none() {
+            recurse_diffs(ctx, paths.push(ItemKey::Index(i)), v1, &absent)?;
+        }
+    }
+    let mut added: Vec<usize> = unpaired2.into_values().flatten().collect();
+    added.sort_unstable();
+    for j in added {
+        recurse_diffs(ctx, paths.push(ItemKey::Index(j)), &absent, &arr2[j])?;
+    }
+    Ok(())
+}
+
+fn recurse_array_diffs(ctx: &mut DiffContext, paths: PathPair, y1: &Yaml, y2: &Yaml) -> Result<()> {
+    if let (Some(arr1),Some(arr2)) = (y1.as_vec(),y2.as_vec()) {
+        match ctx.array_match(&paths.original)? {
+            ArrayMatch::Key(key) => {
+                if let (Some(keyed1),Some(keyed2)) = (keyed_elements(arr1,&key),keyed_elements(arr2,&key)) {
+                    return recurse_keyed_array_diffs(ctx, paths, &key, &keyed1, &keyed2)
+                }
+            }
+            ArrayMatch::Lcs => return recurse_lcs_array_diffs(ctx, paths, arr1, arr2),
+            ArrayMatch::Unordered => return recurse_unordered_array_diffs(ctx, paths, arr1, arr2),
+            ArrayMatch::Position => ()
+        }
+    }
     let empty = Vec::<Yaml>::new();
-    let null_yaml = Yaml::Null;
+    let absent = Yaml::BadValue;
     let arr1 = y1.as_vec().unwrap_or(&empty);
     let arr2 = y2.as_vec().unwrap_or(&empty);
     let max_len = max(arr1.len(),arr2.len());
     for i in 0..max_len {
-        let v1 = if i < arr1.len() { &arr1[i] } else { &null_yaml };
-        let v2 = if i < arr2.len() { &arr2[i] } else { &null_yaml };
-        recurse_diffs(ctx, path.push(ItemKey::Index(i)), v1, v2)?;
+        let v1 = if i < arr1.len() { &arr1[i] } else { &absent };
+        let v2 = if i < arr2.len() { &arr2[i] } else { &absent };
+        recurse_diffs(ctx, paths.push(ItemKey::Index(i)), v1, v2)?;
     }
     if !y1.is_array() {
-        recurse_diffs(ctx, path, y1, &null_yaml)
+        recurse_diffs(ctx, paths, y1, &absent)
     } else if !y2.is_array() {
-        recurse_diffs(ctx, path, &null_yaml,y2)
+        recurse_diffs(ctx, paths, &absent,y2)
     } else {
         Ok(())
     }
 }
 
-fn recurse_hash_diffs<'a,'b>(ctx: &mut DiffContext<'a,'b>, path: KeyPath, y1: &Yaml, y2: &Yaml) -> Result<()> {
+fn recurse_hash_diffs(ctx: &mut DiffContext, paths: PathPair, y1: &Yaml, y2: &Yaml) -> Result<()> {
     let empty = yaml::Hash::new();
-    let null_yaml = Yaml::Null;
+    let absent = Yaml::BadValue;
     let hash1 = y1.as_hash().unwrap_or(&empty);
     let hash2 = y2.as_hash().unwrap_or(&empty);
     for key in hash1.keys() {
         let v1 = &hash1[key];
-        let v2 = if hash2.contains_key(key) { &hash2[key] } else { &null_yaml };
+        let v2 = if hash2.contains_key(key) { &hash2[key] } else { &absent };
         let next_key = ItemKey::Key(key.as_str().unwrap().to_string());
-        recurse_diffs(ctx, path.push(next_key), v1, v2)?;
+        recurse_diffs(ctx, paths.push(next_key), v1, v2)?;
     }
     for key in hash2.keys() {
         let v2 = &hash2[key];
         if !hash1.contains_key(key) {
             let next_key = ItemKey::Key(key.as_str().unwrap().to_string());
-            recurse_diffs(ctx, path.push(next_key), &null_yaml, v2)?;
+            recurse_diffs(ctx, paths.push(next_key), &absent, v2)?;
         }
     }
     if !y1.is_hash() {
-        recurse_diffs(ctx, path, y1, &null_yaml)
+        recurse_diffs(ctx, paths, y1, &absent)
     } else if !y2.is_hash() {
-        recurse_diffs(ctx, path, &null_yaml,y2)
+        recurse_diffs(ctx, paths, &absent,y2)
     } else {
         Ok(())
     }
 }
  
-fn recurse_diffs<'a,'b>(ctx: &mut DiffContext<'a,'b>, path: KeyPath, y1: &Yaml, y2: &Yaml) -> Result<()> {
-    if y1.is_array() || y2.is_array() {
-        recurse_array_diffs(ctx, path, y1, y2)?;
+/** True if the values are both arrays, both hashes or both scalar (or null) */
+fn same_shape(y1: &Yaml, y2: &Yaml) -> bool {
+    y1.is_array() == y2.is_array() && y1.is_hash() == y2.is_hash()
+}
+
+/** True if the value is null, or an empty hash or array */
+pub(crate) fn is_empty_value(value: &Yaml) -> bool {
+    match value {
+        Yaml::Null => true,
+        Yaml::Hash(hash) => hash.is_empty(),
+        Yaml::Array(arr) => arr.is_empty(),
+        _ => false
+    }
+}
+
+/**
+ * Compare values that are each either missing, null, or an empty hash or array, reporting
+ * an empty value found where there is none as added or removed, and otherwise as changed.
+ * An empty document, such as one left by a trailing `---`, isn't added or removed.
+ */
+fn empty_diffs(ctx: &mut DiffContext, paths: PathPair, y1: &Yaml, y2: &Yaml) -> Result<()> {
+    match (y1,y2) {
+        (Yaml::BadValue,_) | (_,Yaml::BadValue) if paths.original.0.is_empty() => (),
+        (Yaml::BadValue,Yaml::BadValue) => (),
+        (Yaml::BadValue,_) => if ctx.path_filter.accept(&paths.modified)? {
+            ctx.diffs.push(Diff::add_empty(ctx.file2.clone(),ctx.docs.clone().unwrap().modified,paths.modified,y2))
+        },
+        (_,Yaml::BadValue) => if ctx.path_filter.accept(&paths.original)? {
+            ctx.diffs.push(Diff::remove_empty(ctx.file1.clone(),ctx.docs.clone().unwrap().original,paths.original,y1))
+        },
+        _ => if *y1 != *y2 && ctx.path_filter.accept(&paths.original)? {
+            ctx.diffs.push(Diff::differ(ctx.file1.clone(),ctx.file2.clone(),ctx.docs.clone().unwrap(),paths,y1,y2))
+        }
+    }
+    Ok(())
+}
+
+fn recurse_diffs(ctx: &mut DiffContext, paths: PathPair, y1: &Yaml, y2: &Yaml) -> Result<()> {
+    let absent = Yaml::BadValue;
+    let vacant = |y: &Yaml| matches!(y,Yaml::BadValue) || is_empty_value(y);
+    let (missing1,missing2) = (ctx.missing(y1),ctx.missing(y2));
+    if vacant(y1) && vacant(y2) {
+        if !ctx.empty_as_missing {
+            empty_diffs(ctx, paths, y1, y2)?;
+        }
+    } else if ctx.subtrees && !same_shape(y1,y2) {
+        if missing1 {
+            if ctx.path_filter.accept(&paths.modified)? {
+                ctx.diffs.push(Diff::add(ctx.file2.clone(),ctx.docs.clone().unwrap().modified,paths.modified,y2))
+            }
+        } else if missing2 {
+            if ctx.path_filter.accept(&paths.original)? {
+                ctx.diffs.push(Diff::remove(ctx.file1.clone(),ctx.docs.clone().unwrap().original,paths.original,y1))
+            }
+        } else if ctx.path_filter.accept(&paths.original)? {
+            ctx.diffs.push(Diff::differ(ctx.file1.clone(),ctx.file2.clone(),ctx.docs.clone().unwrap(),paths,y1,y2))
+        }
+    } else if !ctx.empty_as_missing && !same_shape(y1,y2) && is_empty_value(y1) {
+        empty_diffs(ctx, paths.clone(), y1, &absent)?;
+        recurse_diffs(ctx, paths, &absent, y2)?;
+    } else if !ctx.empty_as_missing && !same_shape(y1,y2) && is_empty_value(y2) {
+        recurse_diffs(ctx, paths.clone(), y1, &absent)?;
+        empty_diffs(ctx, paths, &absent, y2)?;
+    } else if y1.is_array() || y2.is_array() {
+        recurse_array_diffs(ctx, paths, y1, y2)?;
     } else if y1.is_hash() || y2.is_hash() {
-        recurse_hash_diffs(ctx, path, y1, y2)?;
-    } else if ctx.path_filter.accept(&path)? {
-        if y1.is_null() && !y2.is_null() {
-            ctx.diffs.push(Diff::add(&ctx.opts.file2,ctx.dockey.clone().unwrap(),path,y2))
-        } else if !y1.is_null() && y2.is_null() {
-            ctx.diffs.push(Diff::remove(&ctx.opts.file1,ctx.dockey.clone().unwrap(),path,y1))
-        } else if *y1 != *y2 {
-            ctx.diffs.push(Diff::differ(&ctx.opts.file1,&ctx.opts.file2,ctx.dockey.clone().unwrap(),path,y1,y2))
+        recurse_hash_diffs(ctx, paths, y1, y2)?;
+    } else if missing1 {
+        if ctx.path_filter.accept(&paths.modified)? {
+            ctx.diffs.push(Diff::add(ctx.file2.clone(),ctx.docs.clone().unwrap().modified,paths.modified,y2))
+        }
+    } else if missing2 {
+        if ctx.path_filter.accept(&paths.original)? {
+            ctx.diffs.push(Diff::remove(ctx.file1.clone(),ctx.docs.clone().unwrap().original,paths.original,y1))
         }
+    } else if *y1 != *y2 && ctx.path_filter.accept(&paths.original)? {
+        ctx.diffs.push(Diff::differ(ctx.file1.clone(),ctx.file2.clone(),ctx.docs.clone().unwrap(),paths,y1,y2))
     }
     Ok(())
 }
 
-fn find_diffs<'a>(opts: &'a Opts, strategy: &'a Option<Strategy>, d1 : &Documents, d2: &Documents) -> Result<Diffs<'a>> {
-    let null_yaml = Yaml::Null;
+pub fn find_diffs(opts: &DiffOptions, strategy: &Option<Strategy>, d1 : &Documents, d2: &Documents) -> Result<Diffs> {
+    let pairs = pair_similar(d1,d2,opts.similarity);
+    find_renamed_diffs(opts,strategy,d1,d2,&pairs,&Renames::new(),&Renames::new())
+}
+
+/**
+ * Find the differences between two sets of indexed documents, comparing documents of 
+ * different keys that have been paired by similarity. The differences of matched 
+ * documents whose keys differed before transformation, or that were paired by
+ * similarity, are headed by the rename.
+ */
+fn find_renamed_diffs(opts: &DiffOptions, strategy: &Option<Strategy>, d1 : &Documents, d2: &Documents,
+                      pairs: &LinkedHashMap<DocKey,DocKey>, renames1: &Renames, renames2: &Renames) -> Result<Diffs> {
+    let absent = Yaml::BadValue;
     let excludes = opts.exclude_regex()?;
     let path_filter = PathFilter::new(strategy,&excludes);
-    let mut ctx = DiffContext{opts,dockey: None,path_filter: &path_filter, diffs: Diffs::new()};
+    let mut ctx = DiffContext{
+        file1: FileName::from(opts.file1.as_str()),
+        file2: FileName::from(opts.file2.as_str()),
+        strategy,
+        array_default: if opts.lcs { ArrayMatch::Lcs } else { ArrayMatch::Position },
+        ignore_moves: opts.ignore_moves,
+        subtrees: opts.subtrees,
+        empty_as_missing: opts.empty_as_missing,
+        docs: None,
+        path_filter: &path_filter,
+        diffs: Diffs::new()
+    };
     for key in d1.keys() {
-        let path = KeyPath::new();
-        ctx.dockey = Some(Rc::new(key.clone()));
-        if d2.contains_key(key) {
-            recurse_diffs(&mut ctx,path,&d1[key],&d2[key])?;
+        let path = PathPair::new();
+        let paired = if d2.contains_key(key) { Some(key) } else { pairs.get(key) };
+        if let Some(key2) = paired {
+            ctx.docs = Some(DocPair::new(key,key2));
+            let found = ctx.diffs.len();
+            recurse_diffs(&mut ctx,path,&d1[key],&d2[key2])?;
+            let original = renames1.get(key).unwrap_or(key);
+            let modified = renames2.get(key2).unwrap_or(key2);
+            if original != modified && ctx.diffs.len() > found {
+                ctx.diffs.insert(found,Diff::renamed(ctx.file1.clone(),ctx.file2.clone(),DocPair::new(original,modified)));
+            }
         } else {
-            recurse_diffs(&mut ctx,path,&d1[key],&null_yaml)?;
+            ctx.docs = Some(DocPair::new(key,key));
+            recurse_diffs(&mut ctx,path,&d1[key],&absent)?;
         }
     }
+    let paired2: HashSet<&DocKey> = pairs.values().collect();
     for key in d2.keys() {
-        if !d1.contains_key(key) {
-            let path = KeyPath::new();
-            ctx.dockey = Some(Rc::new(key.clone()));
-            recurse_diffs(&mut ctx,path,&null_yaml,&d2[key])?;
+        if !d1.contains_key(key) && !paired2.contains(key) {
+            let path = PathPair::new();
+            ctx.docs = Some(DocPair::new(key,key));
+            recurse_diffs(&mut ctx,path,&absent,&d2[key])?;
         }
     }
     let diffs = ctx.diffs;
     Ok(diffs)
 }
 
-fn new_section<'a>(parent: &mut Option<Location<'a>>, location: &Location<'a>) -> bool {
+fn new_section(parent: &mut Option<Location>, location: &Location) -> bool {
     let new_parent = location.parent();
     if parent.is_some() && new_parent != *parent {
         *parent = new_parent;
@@ -489,78 +1138,133 @@ fn new_section<'a>(parent: &mut Option<Location<'a>>, location: &Location<'a>) -
     }
 }
 
-fn colorize<'a>(opts: &Opts, message: &'a str,remove: bool) -> LzyStr<'a> {
-    if opts.no_colour {
+pub(crate) fn colorize<'a>(coloured: bool, message: &'a str,remove: bool) -> LzyStr<'a> {
+    colorize_with(coloured,message,if remove {ColouredStr::red} else {ColouredStr::green})
+}
+
+pub(crate) fn colorize_with<'a>(coloured: bool, message: &'a str, colour: fn(&mut ColouredStr<'a>)) -> LzyStr<'a> {
+    if !coloured {
         message.into()
     } else {
-        let mut cmessage = ColouredStr::new(&message);
-        if remove {cmessage.red()} else {cmessage.green()}
+        let mut cmessage = ColouredStr::new(message);
+        colour(&mut cmessage);
         format!("{}",cmessage).into()
     }
 }
 
-fn print_location_and_value<'a>(opts: &Opts, lav: &LocationAndValue<'a>,remove: bool) {
+/** True if the value is a hash or array with something in it */
+pub(crate) fn is_subtree(value: &Yaml) -> bool {
+    match value {
+        Yaml::Hash(hash) => !hash.is_empty(),
+        Yaml::Array(arr) => !arr.is_empty(),
+        _ => false
+    }
+}
+
+/** A hash or array as the lines of a YAML block, without the document start marker */
+pub(crate) fn yaml_lines(value: &Yaml) -> Vec<String> {
+    let mut out = String::new();
+    if YamlEmitter::new(&mut out).dump(value).is_err() {
+        return vec![format!("{:?}",value)]
+    }
+    let block = out.strip_prefix("---").unwrap_or(&out).trim_start_matches('\n');
+    block.lines().map(String::from).collect()
+}
+
+fn write_location_and_value(out: &mut dyn io::Write, coloured: bool, lav: &LocationAndValue,remove: bool) -> Result<()> {
     let ostr = lav.value.as_str();
     let chevron = if remove {"<"} else {">"};
-    if ostr.map(|s| s.contains('\n')).unwrap_or(false) {
+    if is_subtree(&lav.value) {
+        let message = format!("{} {} =",chevron,lav.loc);
+        writeln!(out,"{}",colorize(coloured,&message,remove))?;
+        for line in yaml_lines(&lav.value) {
+            let message = format!("  {}",line);
+            writeln!(out,"{}",colorize(coloured,&message,remove))?;
+        }
+    } else if ostr.map(|s| s.contains('\n')).unwrap_or(false) {
         let text = ostr.unwrap();
         let message = format!("{} {} = ...\n{}\n",chevron,lav.loc,text);
-        println!("{}",colorize(opts,&message,remove));
-        if !text.ends_with("\n") { println!(); }
+        writeln!(out,"{}",colorize(coloured,&message,remove))?;
+        if !text.ends_with("\n") { writeln!(out)?; }
     } else {
         let message = format!("{} {}",chevron,lav);
-        println!("{}", colorize(opts,&message,remove));
+        writeln!(out,"{}", colorize(coloured,&message,remove))?;
     }
+    Ok(())
 }
 
 
 
-fn show_diffs<'a>(opts: &Opts, diffs: &'a Diffs<'a>) -> Result<()> {
-    let mut last_parent1: Option<Location<'a>> = None;
-    let mut last_parent2: Option<Location<'a>> = None;
-    if opts.count {
-        let stats = DiffStats::from(diffs);
-        println!("{}",stats);
-    } else {
-        for diff in diffs {
-            match diff {
-                Diff::Add(lav) => {
-                    if new_section(&mut last_parent1, &lav.loc) { println!() }
-                    print_location_and_value(opts,lav,false);
-                }
-                Diff::Remove(lav) => {
-                    if new_section(&mut last_parent2, &lav.loc) { println!() }
-                    print_location_and_value(opts,lav,true);
-                }
-                Diff::Differ(lav1,lav2) => {
-                    let change1 = new_section(&mut last_parent1, &lav1.loc);
-                    let change2 = new_section(&mut last_parent1, &lav2.loc);
-                    if change1 || change2 { println!() }
-                    let str1 = lav1.value.as_str();
-                    let str2 = lav2.value.as_str();
-                    if str1.is_some() && str2.is_some() && (str1.unwrap().contains('\n') || str2.unwrap().contains('\n')) {
-                        let patch = create_patch(str1.unwrap(),str2.unwrap());
-                        let mut f = PatchFormatter::new();
-                        if !opts.no_colour { f = f.with_color() }
-                        let message = format!("< {}",lav1.loc);
-                        println!("{}",colorize(opts,&message,true));
-                        let message = format!("> {}",lav1.loc);
-                        println!("{}",colorize(opts,&message,false));
-                        print!("{}",f.fmt_patch(&patch));
-                    } else {
-                        let message = format!("< {}",lav1);
-                        println!("{}",colorize(opts,&message,true));
-                        let message = format!("> {}",lav2);
-                        println!("{}",colorize(opts,&message,false));
-                    }
+/** Write the differences as human readable text, optionally coloured */
+pub(crate) fn write_diffs(out: &mut dyn io::Write, coloured: bool, diffs: &Diffs) -> Result<()> {
+    write_diffs_in_context(out,coloured,diffs,None)
+}
+
+/** Write the differences as text, each followed by any unchanged values shown with it */
+pub fn write_diffs_in_context(out: &mut dyn io::Write, coloured: bool, diffs: &Diffs, context: Option<&Context>) -> Result<()> {
+    let mut last_parent1: Option<Location> = None;
+    let mut last_parent2: Option<Location> = None;
+    for diff in diffs {
+        match diff {
+            Diff::Add(lav) | Diff::AddEmpty(lav) => {
+                if new_section(&mut last_parent1, &lav.loc) { writeln!(out)? }
+                write_location_and_value(out,coloured,lav,false)?;
+            }
+            Diff::Remove(lav) | Diff::RemoveEmpty(lav) => {
+                if new_section(&mut last_parent2, &lav.loc) { writeln!(out)? }
+                write_location_and_value(out,coloured,lav,true)?;
+            }
+            Diff::Differ(lav1,lav2) => {
+                let change1 = new_section(&mut last_parent1, &lav1.loc);
+                let change2 = new_section(&mut last_parent1, &lav2.loc);
+                if change1 || change2 { writeln!(out)? }
+                let strs = lav1.value.as_str().zip(lav2.value.as_str());
+                if let Some((str1,str2)) = strs.filter(|(s1,s2)| s1.contains('\n') || s2.contains('\n')) {
+                    let patch = create_patch(str1,str2);
+                    let mut f = PatchFormatter::new();
+                    if coloured { f = f.with_color() }
+                    let message = format!("< {}",lav1.loc);
+                    writeln!(out,"{}",colorize(coloured,&message,true))?;
+                    let message = format!("> {}",lav2.loc);
+                    writeln!(out,"{}",colorize(coloured,&message,false))?;
+                    write!(out,"{}",f.fmt_patch(&patch))?;
+                } else if is_subtree(&lav1.value) || is_subtree(&lav2.value) {
+                    write_location_and_value(out,coloured,lav1,true)?;
+                    write_location_and_value(out,coloured,lav2,false)?;
+                } else {
+                    let message = format!("< {}",lav1);
+                    writeln!(out,"{}",colorize(coloured,&message,true))?;
+                    let message = format!("> {}",lav2);
+                    writeln!(out,"{}",colorize(coloured,&message,false))?;
                 }
             }
+            Diff::Move(from,to) => {
+                if new_section(&mut last_parent1, &from.loc) { writeln!(out)? }
+                let target = if from.loc.path == to.loc.path { "(moved)".to_string() } else { format!("-> {}",to.loc.path) };
+                let message = if from.value.is_array() || from.value.is_hash() {
+                    format!("~ {} {}",from.loc,target)
+                } else {
+                    format!("~ {} {} = {:?}",from.loc,target,from.value)
+                };
+                writeln!(out,"{}",colorize_with(coloured,&message,ColouredStr::yellow))?;
+            }
+            Diff::DocumentRenamed(loc1,loc2) => {
+                if last_parent1.is_some() || last_parent2.is_some() { writeln!(out)? }
+                last_parent1 = None;
+                last_parent2 = None;
+                let message = format!("~ {}: {} -> {}: {}",loc1.fname,loc1.doc,loc2.fname,loc2.doc);
+                writeln!(out,"{}",colorize_with(coloured,&message,ColouredStr::yellow))?;
+            }
+        }
+        for (path,value) in context.map(|context| context.values(diff)).unwrap_or_default() {
+            let message = format!("    # {} = {:?}",path,value);
+            writeln!(out,"{}",colorize_with(coloured,&message,ColouredStr::dim))?;
         }
     }
     Ok(())
 }
 
-fn transform_docs(opts: &Opts, strategy: &Option<Strategy>, y1: &mut Vec<Yaml>, y2: &mut Vec<Yaml>) -> Result<()> {
+fn transform_docs(opts: &DiffOptions, strategy: &Option<Strategy>, y1: &mut [Yaml], y2: &mut [Yaml]) -> Result<()> {
     if let Some(strategy) = strategy {
         for (i,y) in y1.iter_mut().enumerate() {
             strategy.transform(y,false)
@@ -574,20 +1278,170 @@ fn transform_docs(opts: &Opts, strategy: &Option<Strategy>, y1: &mut Vec<Yaml>,
     Ok(())
 }
 
-fn diff_docs<'a>(opts: &'a Opts, strategy: &'a Option<Strategy>, mut y1: Vec<Yaml>, mut y2: Vec<Yaml>) -> Result<Diffs<'a>> {
-    transform_docs(opts, strategy, &mut y1, &mut y2)?;
-    let d1 = index(y1,opts,strategy).chain_err(|| format!("while indexing {}",opts.file1))?;
-    let d2 = index(y2,opts,strategy).chain_err(|| format!("while indexing {}",opts.file2))?;
-    find_diffs(opts,strategy,&d1,&d2)
+/**
+ * The differences found in a document of the original file, a document of the modified
+ * file, or a pair of documents compared with each other.
+ */
+pub struct DocumentDiffs<'c> {
+    pub original: Option<&'c DocKey>,
+    pub modified: Option<&'c DocKey>,
+    pub diffs: Diffs
+}
+
+impl<'c> Display for DocumentDiffs<'c> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (self.original,self.modified) {
+            (Some(key1),Some(key2)) if key1 != key2 => write!(f,"{} -> {}",key1,key2),
+            (Some(key),_) | (None,Some(key)) => write!(f,"{}",key),
+            (None,None) => Ok(())
+        }
+    }
 }
 
-pub fn do_diff(opts: &Opts) -> Result<i32> {
-    let strategy = opts.parse_strategy()?;
-    let y1 = load_file(&opts.file1).chain_err(|| format!("while parsing {}",&opts.file1))?;
-    let y2 = load_file(&opts.file2).chain_err(|| format!("while parsing {}",&opts.file2))?;
-    let diffs = diff_docs(opts, &strategy, y1, y2)?;
-    show_diffs(opts,&diffs)?;
-    Ok(if diffs.len() == 0 {0} else {1})
+/** 
+ * The documents of the original and modified files, transformed and indexed ready
+ * for comparison.
+ */
+pub struct Comparison {
+    pub original: Documents,
+    pub modified: Documents,
+    renames1: Renames,
+    renames2: Renames,
+    pairs: LinkedHashMap<DocKey,DocKey>,
+    positions1: HashMap<DocKey,usize>,
+    positions2: HashMap<DocKey,usize>,
+    names1: HashMap<DocKey,Option<(usize,Yaml)>>,
+    names2: HashMap<DocKey,Option<(usize,Yaml)>>,
+    marks1: Vec<Marks>,
+    marks2: Vec<Marks>
+}
+
+impl Comparison {
+    /** Transform and index the parsed documents of each file according to the strategy */
+    pub fn new(opts: &DiffOptions, strategy: &Option<Strategy>, mut y1: Vec<Yaml>, mut y2: Vec<Yaml>) -> Result<Comparison> {
+        let transforms = strategy.as_ref().is_some_and(|s| s.has_transforms());
+        let keys = |docs: &[Yaml]| -> Vec<Option<DocKey>> {
+            if !transforms { return Vec::new() }
+            docs.iter().enumerate().map(|(position,y)| document_key(y,position,opts,strategy).ok()).collect()
+        };
+        let (before1,before2) = (keys(&y1),keys(&y2));
+        transform_docs(opts, strategy, &mut y1, &mut y2)?;
+        let renames1 = find_renames(&before1,&y1,opts,strategy);
+        let renames2 = find_renames(&before2,&y2,opts,strategy);
+        let indexed1 = index_positions(y1,opts,strategy).chain_err(|| format!("while indexing {}",opts.file1))?;
+        let indexed2 = index_positions(y2,opts,strategy).chain_err(|| format!("while indexing {}",opts.file2))?;
+        let pairs = pair_similar(&indexed1.docs,&indexed2.docs,opts.similarity);
+        Ok(Comparison{
+            original: indexed1.docs, modified: indexed2.docs, renames1, renames2, pairs,
+            positions1: indexed1.positions, positions2: indexed2.positions, names1: indexed1.names, names2: indexed2.names,
+            marks1: Vec::new(), marks2: Vec::new()
+        })
+    }
+
+    /** Report the positions of differences, given the positions of values in each file's documents */
+    pub fn with_marks(self, marks1: Vec<Marks>, marks2: Vec<Marks>) -> Comparison {
+        Comparison{marks1,marks2,..self}
+    }
+
+    pub fn diffs(&self, opts: &DiffOptions, strategy: &Option<Strategy>) -> Result<Diffs> {
+        let mut diffs = find_renamed_diffs(opts,strategy,&self.original,&self.modified,&self.pairs,&self.renames1,&self.renames2)?;
+        if !self.marks1.is_empty() || !self.marks2.is_empty() {
+            for diff in &mut diffs {
+                match diff {
+                    Diff::Add(lav) | Diff::AddEmpty(lav) => self.mark(&mut lav.loc,false),
+                    Diff::Remove(lav) | Diff::RemoveEmpty(lav) => self.mark(&mut lav.loc,true),
+                    Diff::Differ(lav1,lav2) | Diff::Move(lav1,lav2) => {
+                        self.mark(&mut lav1.loc,true);
+                        self.mark(&mut lav2.loc,false);
+                    }
+                    Diff::DocumentRenamed(_,_) => ()
+                }
+            }
+        }
+        Ok(diffs)
+    }
+
+    /**
+     * The differences found between a document of the original file and one of the
+     * modified file, including any rename. A document found in only one file is given
+     * as both documents.
+     */
+    pub fn diffs_between<'d>(&self, diffs: &'d Diffs, original: &DocKey, modified: &DocKey) -> Vec<&'d Diff> {
+        let renamed_from = self.renames1.get(original).unwrap_or(original);
+        let renamed_to = self.renames2.get(modified).unwrap_or(modified);
+        diffs.iter().filter(|diff| match diff {
+            Diff::DocumentRenamed(loc1,loc2) => *loc1.doc == *renamed_from && *loc2.doc == *renamed_to,
+            _ => diff.is_between(original,modified)
+        }).collect()
+    }
+
+    /**
+     * The differences found in each document, in the order of the original file followed
+     * by any documents found only in the modified file.
+     */
+    pub fn document_diffs(&self, diffs: &Diffs) -> Vec<DocumentDiffs<'_>> {
+        let pairs = self.document_pairs();
+        let paired = |key: &DocKey| pairs.iter().find(|(key1,_)| *key1 == key).map(|(_,key2)| *key2);
+        let mut documents: Vec<(Option<&DocKey>,Option<&DocKey>)> = self.original.keys().map(|key| (Some(key),paired(key))).collect();
+        documents.extend(self.modified.keys().filter(|key| !pairs.iter().any(|(_,key2)| key2 == key)).map(|key| (None,Some(key))));
+        documents.into_iter().map(|(original,modified)| {
+            let (key1,key2) = (original.or(modified).unwrap(),modified.or(original).unwrap());
+            DocumentDiffs{original,modified,diffs: self.diffs_between(diffs,key1,key2).into_iter().cloned().collect()}
+        }).collect()
+    }
+
+    /**
+     * A document of the original or modified file as loaded and transformed, without
+     * any changes made to it when it was indexed.
+     */
+    pub fn document(&self, key: &DocKey, original: bool) -> Option<Cow<'_,Yaml>> {
+        let (docs,names) = if original { (&self.original,&self.names1) } else { (&self.modified,&self.names2) };
+        let doc = docs.get(key)?;
+        Some(match names.get(key) {
+            Some(replaced) => Cow::Owned(unindexed(doc,replaced)),
+            None => Cow::Borrowed(doc)
+        })
+    }
+
+    fn mark(&self, loc: &mut Location, original: bool) {
+        loc.mark = self.position(&loc.doc,&loc.path,original);
+    }
+
+    /**
+     * The position of the value at a path in a document of the original or modified file,
+     * or else of its closest ancestor in the document. Element matches are resolved against
+     * the transformed document, so the position of a value within an array reordered by the
+     * strategy may be that of its array.
+     */
+    pub fn position(&self, doc: &DocKey, path: &KeyPath, original: bool) -> Option<Mark> {
+        let (docs,positions,marks) = if original {
+            (&self.original,&self.positions1,&self.marks1)
+        } else {
+            (&self.modified,&self.positions2,&self.marks2)
+        };
+        let (yaml,marks) = (docs.get(doc)?,marks.get(*positions.get(doc)?)?);
+        (0..=path.0.len()).rev().find_map(|len| path.truncated(len).resolved(yaml).ok())
+            .and_then(|path| find_mark(marks,&path))
+    }
+
+    /** 
+     * The keys of the documents compared with each other, in the order of the original 
+     * file. Documents found in only one file are not included.
+     */
+    pub fn document_pairs(&self) -> Vec<(&DocKey,&DocKey)> {
+        self.original.keys().filter_map(|key| {
+            if self.modified.contains_key(key) { Some((key,key)) } else { self.pairs.get(key).map(|key2| (key,key2)) }
+        }).collect()
+    }
+}
+
+/**
+ * Compare two sets of parsed YAML documents, returning the differences found. Documents
+ * are transformed and filtered according to the strategy, if one is given, and then
+ * matched by position or Kubernetes metadata before being compared.
+ */
+pub fn diff_docs(opts: &DiffOptions, strategy: &Option<Strategy>, y1: Vec<Yaml>, y2: Vec<Yaml>) -> Result<Diffs> {
+    Comparison::new(opts,strategy,y1,y2)?.diffs(opts,strategy)
 }
 
 #[cfg(test)]
@@ -615,7 +1469,7 @@ mod test {
     fn test_singledoc() {
         let original = fixture("singledoc/original.yaml");
         let modified = fixture("singledoc/modified.yaml");
-        let opts = Opts::new();
+        let opts = DiffOptions::default();
         let strategy = None;
         let diffs = diff_docs(&opts,&strategy,original,modified).unwrap();
         assert_eq!(2,diffs.len());
@@ -640,6 +1494,26 @@ mod test {
         }
     }   
 
+    #[test]
+    fn test_diffs_outlive_options() {
+        let diffs = {
+            let opts = DiffOptions::new("original.yaml","modified.yaml");
+            let original = fixture("singledoc/original.yaml");
+            let modified = fixture("singledoc/modified.yaml");
+            diff_docs(&opts,&None,original,modified).unwrap()
+        };
+        let shared = diffs.clone();
+        let handle = std::thread::spawn(move || DiffStats::from(&diffs).total);
+        assert_eq!(2,handle.join().unwrap());
+        match &shared[0] {
+            Diff::Differ(o,m) => {
+                assert_eq!("original.yaml",&*o.loc.fname);
+                assert_eq!("modified.yaml",&*m.loc.fname);
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+    }
+
     #[test]
     fn test_regexfilter() {
         let test_strat = r#"
@@ -651,8 +1525,7 @@ mod test {
         let original = fixture("vault1.yaml");
         let modified = fixture("vault2.yaml");
         let strategy = Some(Strategy::from_str(test_strat).unwrap());
-        let mut opts = Opts::new();
-        opts.k8s = true;
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
         let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
         assert_eq!(26,diffs.len());
     }
@@ -669,8 +1542,7 @@ mod test {
         let original = fixture("vault1.yaml");
         let modified = fixture("vault2.yaml");
         let strategy = Some(Strategy::from_str(test_strat).unwrap());
-        let mut opts = Opts::new();
-        opts.k8s = true;
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
         let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
         assert_eq!(34,diffs.len());
     }
@@ -692,19 +1564,337 @@ mod test {
         let original = fixture("vault1.yaml");
         let modified = fixture("vault2.yaml");
         let strategy = Some(Strategy::from_str(test_strat).unwrap());
-        let mut opts = Opts::new();
-        opts.k8s = true;
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
         let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
         assert_eq!(0,diffs.len());
     }
 
+    #[test]
+    fn test_keyed_arrays() {
+        let test_strat = r#"
+        arrays:
+          - path: spec.template.spec.containers
+            key: name
+        "#;
+        let original = fixture("keyed-arrays/original.yaml");
+        let modified = fixture("keyed-arrays/modified.yaml");
+        let opts = DiffOptions::default();
+        let diffs = diff_docs(&opts, &None, original.clone(), modified.clone()).unwrap();
+        assert_eq!(10,diffs.len());
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
+        let paths: Vec<String> = diffs.iter().map(|d| d.key_path().to_string()).collect();
+        assert_eq!(vec![
+            "spec.template.spec.containers[name=app].image",
+            "spec.template.spec.containers[name=sidecar].name",
+            "spec.template.spec.containers[name=sidecar].image"
+        ],paths);
+        let stats = DiffStats::from(&diffs);
+        assert_eq!(1,stats.changes);
+        assert_eq!(2,stats.additions);
+    }
+
+    #[test]
+    fn test_k8s_merge_keys() {
+        let original = fixture("keyed-arrays/original.yaml");
+        let modified = fixture("keyed-arrays/modified.yaml");
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
+        let diffs = diff_docs(&opts, &None, original, modified).unwrap();
+        let paths: Vec<String> = diffs.iter().map(|d| d.key_path().to_string()).collect();
+        assert_eq!(vec![
+            "spec.template.spec.containers[name=app].image",
+            "spec.template.spec.containers[name=sidecar].name",
+            "spec.template.spec.containers[name=sidecar].image"
+        ],paths);
+    }
+
+    #[test]
+    fn test_marked_locations() {
+        let (original,marks1) = load_file_marked(&fixture_fname("keyed-arrays/original.yaml")).unwrap();
+        let (modified,marks2) = load_file_marked(&fixture_fname("keyed-arrays/modified.yaml")).unwrap();
+        let opts = DiffOptions{k8s: true, ..DiffOptions::new("original.yaml","modified.yaml")};
+        let comparison = Comparison::new(&opts, &None, original, modified).unwrap().with_marks(marks1, marks2);
+        let diffs = comparison.diffs(&opts, &None).unwrap();
+        match &diffs[0] {
+            Diff::Differ(lav1,lav2) => {
+                assert_eq!(Some(Mark{line: 12, col: 11}),lav1.loc.mark);
+                assert_eq!("modified.yaml:14:11: apps/v1,Deployment,web/default spec.template.spec.containers[name=app].image",lav2.loc.to_string());
+            }
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+    }
+
+    #[test]
+    fn test_leaf_stats() {
+        let (text1,text2) = ("a: 1\n","a: 1\nn: {x: ~, y: [1, ~], e: {}}\n");
+        let find = |opts: &DiffOptions| diff_docs(opts,&None,YamlLoader::load_from_str(text1).unwrap(),YamlLoader::load_from_str(text2).unwrap()).unwrap();
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        let subtrees = find(&DiffOptions{subtrees: true, ..opts.clone()});
+        assert_eq!("4 differences (additions: 1, empty values: 3)",DiffStats::from(&find(&opts)).to_string());
+        assert_eq!("4 differences (additions: 1, empty values: 3)",DiffStats::from_leaves(&subtrees,&opts).to_string());
+        let opts = DiffOptions{empty_as_missing: true, ..opts};
+        assert_eq!("1 difference (additions: 1)",DiffStats::from(&find(&opts)).to_string());
+        assert_eq!("1 difference (additions: 1)",DiffStats::from_leaves(&subtrees,&opts).to_string());
+    }
+
+    #[test]
+    fn test_subtrees() {
+        let original = fixture("keyed-arrays/original.yaml");
+        let modified = fixture("keyed-arrays/modified.yaml");
+        let opts = DiffOptions{k8s: true, ..DiffOptions::new("original.yaml","modified.yaml")};
+        assert_eq!(3,diff_docs(&opts, &None, original.clone(), modified.clone()).unwrap().len());
+        let opts = DiffOptions{subtrees: true, ..opts};
+        let diffs = diff_docs(&opts, &None, original, modified).unwrap();
+        assert_eq!(2,DiffStats::from(&diffs).total);
+        let stats = DiffStats::from_leaves(&diffs,&opts);
+        assert_eq!((3,2,1),(stats.total,stats.additions,stats.changes));
+        let mut out = Vec::new();
+        write_diffs(&mut out,false,&vec![diffs[1].clone()]).unwrap();
+        assert_eq!(concat!(
+            "> modified.yaml: apps/v1,Deployment,web/default spec.template.spec.containers[name=sidecar] =\n",
+            "  name: sidecar\n",
+            "  image: \"proxy:0.9\"\n"),String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_empty_documents() {
+        let find = |text1: &str, text2: &str| diff_docs(&DiffOptions::default(),&None,YamlLoader::load_from_str(text1).unwrap(),YamlLoader::load_from_str(text2).unwrap()).unwrap();
+        assert!(find("a: 1\n---\n","a: 1\n").is_empty());
+        assert!(find("a: 1\n","a: 1\n---\n---\n").is_empty());
+        let diffs = find("a: 1\n---\n","a: 1\n---\nb: 2\n");
+        assert_eq!(1,diffs.len());
+        assert_eq!("f.yaml: [1]",Location::new(FileName::from("f.yaml"),Arc::new(DocKey::Position(1)),KeyPath::new()).to_string());
+    }
+
+    #[test]
+    fn test_empty_values() {
+        let text1 = "resources: {}\nargs: []\nkey: null\nlimits: {cpu: 1}\nenv: []\nports: null\n";
+        let text2 = "limits: null\nenv: {}\nports: 80\nlabels: {}\n";
+        let find = |opts: &DiffOptions| diff_docs(opts,&None,YamlLoader::load_from_str(text1).unwrap(),YamlLoader::load_from_str(text2).unwrap()).unwrap();
+        let diffs = |opts: &DiffOptions| {
+            find(opts).iter().map(|diff| match diff {
+                Diff::Add(lav) => format!("add {}",lav.loc.path),
+                Diff::Remove(lav) => format!("remove {}",lav.loc.path),
+                Diff::AddEmpty(lav) => format!("add empty {} = {:?}",lav.loc.path,lav.value),
+                Diff::RemoveEmpty(lav) => format!("remove empty {} = {:?}",lav.loc.path,lav.value),
+                Diff::Differ(lav1,lav2) => format!("change {} = {:?} -> {:?}",lav1.loc.path,lav1.value,lav2.value),
+                diff => panic!("Unexpected diff {:?}",diff)
+            }).collect::<Vec<String>>()
+        };
+        let opts = DiffOptions::new("f1.yaml","f2.yaml");
+        assert_eq!(vec![
+            "remove empty resources = Hash({})",
+            "remove empty args = Array([])",
+            "remove empty key = Null",
+            "remove limits.cpu",
+            "add empty limits = Null",
+            "change env = Array([]) -> Hash({})",
+            "change ports = Null -> Integer(80)",
+            "add empty labels = Hash({})"
+        ],diffs(&opts));
+        let stats = DiffStats::from(&find(&opts));
+        assert_eq!((8,5,1),(stats.total,stats.empties,stats.removals));
+        let opts = DiffOptions{empty_as_missing: true, ..opts};
+        assert_eq!(vec!["remove limits.cpu","add ports"],diffs(&opts));
+        let opts = DiffOptions{subtrees: true, ..opts};
+        assert_eq!(vec!["remove limits","add ports"],diffs(&opts));
+    }
+
+    #[test]
+    fn test_lcs_arrays() {
+        let original = fixture("lcs/original.yaml");
+        let modified = fixture("lcs/modified.yaml");
+        let opts = DiffOptions::default();
+        let diffs = diff_docs(&opts, &None, original.clone(), modified.clone()).unwrap();
+        assert_eq!(6,diffs.len());
+        let opts = DiffOptions{lcs: true, ..DiffOptions::default()};
+        let diffs = diff_docs(&opts, &None, original, modified).unwrap();
+        assert_eq!(3,diffs.len());
+        match &diffs[0] {
+            Diff::Add(lav) => {
+                assert_eq!("command[1]",lav.loc.path.to_string());
+                assert_eq!("--debug",lav.value.as_str().unwrap());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        match &diffs[1] {
+            Diff::Differ(o,m) => {
+                assert_eq!("command[2]",o.loc.path.to_string());
+                assert_eq!("command[3]",m.loc.path.to_string());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        match &diffs[2] {
+            Diff::Remove(lav) => {
+                assert_eq!("hosts[1]",lav.loc.path.to_string());
+                assert_eq!("beta.example.com",lav.value.as_str().unwrap());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+    }
+
+    #[test]
+    fn test_lcs_arrays_strat() {
+        let test_strat = r#"
+        arrays:
+          - path: hosts
+            compare: lcs
+        "#;
+        let original = fixture("lcs/original.yaml");
+        let modified = fixture("lcs/modified.yaml");
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let opts = DiffOptions::default();
+        let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
+        let stats = DiffStats::from(&diffs);
+        assert_eq!(5,stats.total);
+        assert_eq!(1,stats.removals);
+    }
+
+    #[test]
+    fn test_unordered_arrays() {
+        let test_strat = r#"
+        arrays:
+          - regex: (finalizers|resources|verbs)$
+            compare: unordered
+        "#;
+        let original = fixture("unordered-arrays/original.yaml");
+        let modified = fixture("unordered-arrays/modified.yaml");
+        let opts = DiffOptions::default();
+        let diffs = diff_docs(&opts, &None, original.clone(), modified.clone()).unwrap();
+        assert_eq!(8,diffs.len());
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
+        assert_eq!(1,diffs.len());
+        match &diffs[0] {
+            Diff::Add(lav) => {
+                assert_eq!("rules[0].verbs[3]",lav.loc.path.to_string());
+                assert_eq!("delete",lav.value.as_str().unwrap());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+    }
+
+    #[test]
+    fn test_moves() {
+        let test_strat = r#"
+        arrays:
+          - path: containers
+            key: name
+          - path: args
+            compare: lcs
+        "#;
+        let original = fixture("moves/original.yaml");
+        let modified = fixture("moves/modified.yaml");
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let opts = DiffOptions::default();
+        let diffs = diff_docs(&opts, &strategy, original.clone(), modified.clone()).unwrap();
+        assert_eq!(3,diffs.len());
+        match &diffs[0] {
+            Diff::Differ(o,_) => assert_eq!("containers[name=app].image",o.loc.path.to_string()),
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        match &diffs[1] {
+            Diff::Move(from,to) => {
+                assert_eq!("containers[name=logger]",from.loc.path.to_string());
+                assert_eq!("containers[name=logger]",to.loc.path.to_string());
+                assert_eq!("logger",from.value["name"].as_str().unwrap());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        match &diffs[2] {
+            Diff::Move(from,to) => {
+                assert_eq!("args[0]",from.loc.path.to_string());
+                assert_eq!("args[3]",to.loc.path.to_string());
+                assert_eq!("--alpha",to.value.as_str().unwrap());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        let stats = DiffStats::from(&diffs);
+        assert_eq!(2,stats.moves);
+        assert_eq!(1,stats.changes);
+        let opts = DiffOptions{ignore_moves: true, ..DiffOptions::default()};
+        let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
+        assert_eq!(1,diffs.len());
+    }
+
+    #[test]
+    fn test_document_match_keys() {
+        let test_strat = r#"
+        match:
+          keys: [kind, spec.id]
+        "#;
+        let original = fixture("keyed-docs/original.yaml");
+        let modified = fixture("keyed-docs/modified.yaml");
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let opts = DiffOptions::default();
+        let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
+        assert_eq!(2,diffs.len());
+        match &diffs[0] {
+            Diff::Differ(o,m) => {
+                assert_eq!("kind=Queue,spec.id=payments",o.loc.doc.to_string());
+                assert_eq!("spec.retention",o.loc.path.to_string());
+                assert_eq!("14d",m.value.as_str().unwrap());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        match &diffs[1] {
+            Diff::Differ(o,_) => {
+                assert_eq!(DocKey::Properties(vec![
+                    ("kind".to_string(),"Topic".to_string()),
+                    ("spec.id".to_string(),"orders".to_string())
+                ]),*o.loc.doc);
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+    }
+
+    #[test]
+    fn test_document_match_missing_key() {
+        let test_strat = r#"
+        match:
+          keys: [kind, spec.name]
+        "#;
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let original = fixture("keyed-docs/original.yaml");
+        let result = index(original, &DiffOptions::default(), &strategy);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!("key 'spec.name' not found in YAML document, or is wrong type",e.to_string());
+        }
+    }
+
+    #[test]
+    fn test_comparison_document() {
+        let text = "apiVersion: v1\nkind: ConfigMap\nmetadata: {name: a}\n---\nname: top\napiVersion: v1\nkind: ConfigMap\nmetadata: {name: b}\n";
+        let docs = YamlLoader::load_from_str(text).unwrap();
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
+        let comparison = Comparison::new(&opts,&None,docs.clone(),docs.clone()).unwrap();
+        let keys: Vec<&DocKey> = comparison.original.keys().collect();
+        assert_eq!(Yaml::String("myvalue".to_string()),comparison.original[keys[1]]["name"]);
+        assert_eq!(Some(&docs[0]),comparison.document(keys[0],true).as_deref());
+        assert_eq!(Some(&docs[1]),comparison.document(keys[1],false).as_deref());
+    }
+
+    #[test]
+    fn test_document_match_duplicate_key() {
+        let test_strat = r#"
+        match:
+          keys: [kind]
+        "#;
+        let strategy = Some(Strategy::from_str(test_strat).unwrap());
+        let original = fixture("keyed-docs/original.yaml");
+        match index(original, &DiffOptions::default(), &strategy) {
+            Err(e) => assert_eq!("more than one document has the key 'kind=Queue'",e.to_string()),
+            Ok(_) => panic!("Duplicate document key not reported")
+        }
+    }
+
     #[test]
     fn test_unordered_renamed() {
         let original = fixture("unordered-renamed-k8s/vault1.yaml");
         let modified = fixture("unordered-renamed-k8s/vault2.yaml");
         let strategy = None;
-        let mut opts = Opts::new();
-        opts.k8s = true;
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
         let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
         let stats = DiffStats::from(&diffs);
         assert_eq!(34,stats.total);
@@ -713,18 +1903,58 @@ mod test {
         assert_eq!(0,stats.changes);
     }
 
+    #[test]
+    fn test_unordered_renamed_similarity() {
+        let original = fixture("unordered-renamed-k8s/vault1.yaml");
+        let modified = fixture("unordered-renamed-k8s/vault2.yaml");
+        let opts = DiffOptions{k8s: true, similarity: Some(0.5), ..DiffOptions::default()};
+        let diffs = diff_docs(&opts, &None, original, modified).unwrap();
+        let stats = DiffStats::from(&diffs);
+        assert_eq!(4,stats.total);
+        assert_eq!(4,stats.changes);
+        assert_eq!(2,stats.renames);
+        match &diffs[0] {
+            Diff::DocumentRenamed(o,m) => {
+                assert_eq!("v1,ServiceAccount,vault1/default",o.doc.to_string());
+                assert_eq!("v1,ServiceAccount,vault2/default",m.doc.to_string());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        match &diffs[1] {
+            Diff::Differ(o,m) => {
+                assert_eq!("metadata.name",o.loc.path.to_string());
+                assert_eq!("v1,ServiceAccount,vault1/default",o.loc.doc.to_string());
+                assert_eq!("v1,ServiceAccount,vault2/default",m.loc.doc.to_string());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
+        let opts = DiffOptions{k8s: true, similarity: Some(0.9), ..DiffOptions::default()};
+        let original = fixture("unordered-renamed-k8s/vault1.yaml");
+        let modified = fixture("unordered-renamed-k8s/vault2.yaml");
+        let diffs = diff_docs(&opts, &None, original, modified).unwrap();
+        assert_eq!(34,diffs.len());
+    }
+
     #[test]
     fn test_unordered_renamed_strat() {
         let original = fixture("unordered-renamed-k8s/vault1.yaml");
         let modified = fixture("unordered-renamed-k8s/vault2.yaml");
         let strategy_text = fixture_text("unordered-renamed-k8s/strat.yaml");
         let strategy = Some(Strategy::from_str(&strategy_text).unwrap());
-        let mut opts = Opts::new();
-        opts.k8s = true;
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
         let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
         let stats = DiffStats::from(&diffs);
         assert_eq!(2,stats.total);
         assert_eq!(2,stats.changes);
+        assert_eq!(2,stats.renames);
+        assert_eq!("2 differences (changes: 2), 2 documents renamed",stats.to_string());
+        match &diffs[0] {
+            Diff::DocumentRenamed(o,m) => {
+                assert_eq!("v1,ServiceAccount,vault1/default",o.doc.to_string());
+                assert_eq!("v1,ServiceAccount,vault2/default",m.doc.to_string());
+            },
+            diff => panic!("Unexpected diff {:?}",diff)
+        }
     }
 
     #[test]
@@ -733,8 +1963,7 @@ mod test {
         let modified = fixture("unordered-renamed-k8s/vault2.yaml");
         let strategy_text = fixture_text("unordered-renamed-k8s/strat-filter.yaml");
         let strategy = Some(Strategy::from_str(&strategy_text).unwrap());
-        let mut opts = Opts::new();
-        opts.k8s = true;
+        let opts = DiffOptions{k8s: true, ..DiffOptions::default()};
         let diffs = diff_docs(&opts, &strategy, original, modified).unwrap();
         let stats = DiffStats::from(&diffs);
         assert_eq!(0,stats.total);